@@ -0,0 +1,223 @@
+//! Images and attachments extracted from notebooks. Files are named after a hash of their content,
+//! so repeated conversions produce the same names, unchanged files are not written again, and
+//! several notebooks can share one directory.
+
+use crate::{J2TError, STDIO_PATH};
+
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Numbers temporary files, so that concurrent writers don't share them.
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+pub struct Assets {
+    /// Directory the files are written into.
+    dir: PathBuf,
+    /// `dir` as referenced from the generated Typst source.
+    link: String,
+    /// Only compute file names, without writing anything.
+    dry_run: bool,
+}
+
+impl Assets {
+    /// Store assets in `dir`, or in `<outfile>_assets` next to the output file by default.
+    pub fn new(dir: Option<&str>, infile: &str, outfile: &str) -> Assets {
+        let dir = match dir {
+            Some(dir) => PathBuf::from(dir),
+            None => default_dir(infile, outfile),
+        };
+        let out_dir = if outfile == STDIO_PATH {
+            Path::new("")
+        } else {
+            Path::new(outfile).parent().unwrap_or(Path::new(""))
+        };
+        let link = relative_path(out_dir, &dir)
+            .to_string_lossy()
+            .replace('\\', "/");
+        Assets {
+            dir,
+            link,
+            dry_run: false,
+        }
+    }
+
+    /// Like `new`, but never write any files.
+    pub fn dry_run(dir: Option<&str>, infile: &str) -> Assets {
+        Assets {
+            dry_run: true,
+            ..Assets::new(dir, infile, STDIO_PATH)
+        }
+    }
+
+    /// Store `data` in a file with the given extension, and return the path under which the
+    /// generated document refers to it.
+    pub fn store(&self, data: &[u8], extension: &str) -> Result<String, J2TError> {
+        let name = format!("{:016x}.{}", fnv1a(data), extension);
+        let path = self.dir.join(&name);
+        if !self.dry_run && !path.is_file() {
+            fs::create_dir_all(&self.dir)?;
+            // Cells are converted in parallel, so the same image may be stored twice at once.
+            // Renaming makes sure that nobody sees a partially written file.
+            let temp = self.dir.join(format!(
+                "{}.{}.tmp",
+                name,
+                TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+            ));
+            fs::write(&temp, data)?;
+            fs::rename(&temp, &path)?;
+        }
+        if self.link.is_empty() {
+            Ok(name)
+        } else {
+            Ok(format!("{}/{}", self.link, name))
+        }
+    }
+
+    /// The directory the files are written into.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The directory as referenced from the generated Typst source.
+    pub fn link(&self) -> &str {
+        &self.link
+    }
+
+    /// Whether nothing is written.
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// Whether the file `path`, as returned by `store`, exists.
+    pub fn exists(&self, path: &str) -> bool {
+        let name = if self.link.is_empty() {
+            path
+        } else {
+            match path
+                .strip_prefix(&self.link)
+                .and_then(|p| p.strip_prefix('/'))
+            {
+                Some(name) => name,
+                None => return false,
+            }
+        };
+        self.dir.join(name).is_file()
+    }
+}
+
+fn default_dir(infile: &str, outfile: &str) -> PathBuf {
+    let base = if outfile != STDIO_PATH {
+        Path::new(outfile).with_extension("")
+    } else if infile != STDIO_PATH {
+        // Written to stdout: use the notebook's name in the working directory.
+        PathBuf::from(Path::new(infile).file_stem().unwrap_or_default())
+    } else {
+        PathBuf::from("notebook")
+    };
+    let mut name = base.into_os_string();
+    name.push("_assets");
+    PathBuf::from(name)
+}
+
+/// The path `to` as seen from the directory `from`. Both must be either relative or absolute.
+fn relative_path(from: &Path, to: &Path) -> PathBuf {
+    fn normal(p: &Path) -> Vec<Component<'_>> {
+        p.components().filter(|c| *c != Component::CurDir).collect()
+    }
+    let (from, to) = (normal(from), normal(to));
+    let common = from
+        .iter()
+        .zip(to.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut path = PathBuf::new();
+    for _ in common..from.len() {
+        path.push("..");
+    }
+    path.extend(&to[common..]);
+    path
+}
+
+/// The 64-bit FNV-1a hash of `data`. Unlike `std`'s hashers, it is stable across Rust versions.
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, b| {
+        (hash ^ *b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Decode base64 as used for binary output data in notebooks. Whitespace, which may appear
+/// between lines, is skipped.
+pub fn decode_base64(s: &str) -> Result<Vec<u8>, String> {
+    let mut out = Vec::with_capacity(s.len() * 3 / 4);
+    let (mut buf, mut bits) = (0u32, 0);
+    for c in s.bytes().filter(|c| !c.is_ascii_whitespace()) {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            b'=' => break,
+            _ => return Err(format!("invalid base64 character '{}'", c as char)),
+        };
+        buf = buf << 6 | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buf >> bits) as u8);
+        }
+    }
+    Ok(out)
+}
+
+/// Encode `data` as base64, the way notebooks store binary output data.
+pub fn encode_base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity((data.len() + 2) / 3 * 4);
+    for chunk in data.chunks(3) {
+        let buf = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |buf, (i, &b)| buf | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(buf >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// The file extension for image data of MIME type `mime`, if it is an image type we extract.
+pub fn image_extension(mime: &str) -> Option<&'static str> {
+    match mime {
+        "image/png" => Some("png"),
+        "image/jpeg" => Some("jpg"),
+        "image/gif" => Some("gif"),
+        "image/svg+xml" => Some("svg"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base64() {
+        for (data, encoded) in [
+            (&b""[..], ""),
+            (b"f", "Zg=="),
+            (b"fo", "Zm8="),
+            (b"foo", "Zm9v"),
+            (b"foob", "Zm9vYg=="),
+            (b"\xff\xfe\x00", "//4A"),
+        ] {
+            assert_eq!(encode_base64(data), encoded);
+            assert_eq!(decode_base64(encoded).as_deref(), Ok(data));
+        }
+    }
+}
@@ -0,0 +1,305 @@
+//! Cache of converted cells (`--cache`), so that repeated conversions of a large notebook only
+//! convert the cells that changed.
+//!
+//! An entry holds the Typst markup of a cell, named after a hash of the cell's JSON and of
+//! everything else its conversion depends on: the options, the code language, heading offset,
+//! math macros and where assets are linked from. Its first line lists the assets the markup
+//! refers to; the entry is only used while they exist. Cells with warnings aren't cached, so that
+//! the warnings are reported on every run. Entries not used for `MAX_AGE` are removed.
+
+use crate::assets::Assets;
+use crate::config::Options;
+
+use tinyjson::JsonValue;
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Once;
+use std::time::{Duration, SystemTime};
+
+/// Directory of the cache, relative to the working directory.
+pub const CACHE_DIR: &str = ".jupyter2typst-cache";
+
+/// Starts the first line of an entry, followed by the assets.
+const ASSETS_HEADER: &str = "// assets:";
+
+/// Entries not used for this long are removed.
+const MAX_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// Numbers temporary files, so that concurrent writers don't share them.
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Stale entries are removed once per run.
+static EVICT: Once = Once::new();
+
+/// Incremental 64-bit FNV-1a hash, which is stable across runs and Rust versions.
+struct Fnv(u64);
+
+impl Fnv {
+    fn new() -> Fnv {
+        Fnv(0xcbf29ce484222325)
+    }
+
+    fn write(&mut self, data: &[u8]) {
+        for b in data {
+            self.0 = (self.0 ^ *b as u64).wrapping_mul(0x100000001b3);
+        }
+    }
+
+    /// Write `s`, terminated so that consecutive strings can't run into each other.
+    fn write_str(&mut self, s: &str) {
+        self.write(s.as_bytes());
+        self.write(&[0xff]);
+    }
+}
+
+/// Hashes of the cells of the notebook `nb`, in order.
+pub fn cell_fingerprints(nb: &JsonValue) -> Vec<u64> {
+    match nb
+        .get::<HashMap<String, JsonValue>>()
+        .and_then(|nb| nb.get("cells"))
+    {
+        Some(JsonValue::Array(cells)) => cells.iter().map(cell_fingerprint).collect(),
+        _ => vec![],
+    }
+}
+
+/// Hash of the JSON of a cell. Object keys are sorted, as their order in `JsonValue` isn't
+/// stable.
+fn cell_fingerprint(json: &JsonValue) -> u64 {
+    fn hash(value: &JsonValue, h: &mut Fnv) {
+        match value {
+            JsonValue::Number(n) => {
+                h.write(b"n");
+                h.write(&n.to_bits().to_le_bytes());
+            }
+            JsonValue::Boolean(b) => h.write(if *b { b"t" } else { b"f" }),
+            JsonValue::String(s) => {
+                h.write(b"s");
+                h.write_str(s);
+            }
+            JsonValue::Null => h.write(b"0"),
+            JsonValue::Array(values) => {
+                h.write(b"[");
+                values.iter().for_each(|v| hash(v, h));
+                h.write(b"]");
+            }
+            JsonValue::Object(object) => {
+                h.write(b"{");
+                let mut keys = object.keys().collect::<Vec<_>>();
+                keys.sort();
+                for key in keys {
+                    h.write_str(key);
+                    hash(&object[key], h);
+                }
+                h.write(b"}");
+            }
+        }
+    }
+    let mut h = Fnv::new();
+    hash(json, &mut h);
+    h.0
+}
+
+pub struct CellCache<'a> {
+    dir: PathBuf,
+    /// Hash of everything besides the cell that conversion depends on.
+    salt: u64,
+    assets: &'a Assets,
+}
+
+impl<'a> CellCache<'a> {
+    /// The cache for cells converted with `opts`, or `None` if caching is off. `context` describes
+    /// what else the conversion depends on. Nothing is cached without writing assets.
+    pub fn new(opts: &Options, assets: &'a Assets, context: &str) -> Option<CellCache<'a>> {
+        if !opts.cache || assets.is_dry_run() {
+            return None;
+        }
+        let dir = PathBuf::from(CACHE_DIR);
+        EVICT.call_once(|| evict(&dir));
+        Some(CellCache::in_dir(dir, opts, assets, context))
+    }
+
+    fn in_dir(dir: PathBuf, opts: &Options, assets: &'a Assets, context: &str) -> CellCache<'a> {
+        let mut h = Fnv::new();
+        h.write_str(env!("CARGO_PKG_VERSION"));
+        // The options read while converting cells. Others, like `compile` or `token`, don't
+        // change the markup of a cell; `lang` and `lang_map` are covered by `context`.
+        let options: [&dyn Debug; 16] = [
+            &opts.skip_unexecuted,
+            &opts.respect_collapse,
+            &opts.prompts,
+            &opts.line_numbers,
+            &opts.wrap_code,
+            &opts.wrap_output,
+            &opts.nbgrader,
+            &opts.mime_priority,
+            &opts.layout,
+            &opts.smartquotes,
+            &opts.emoji_font,
+            &opts.tableize_output,
+            &opts.split_by_heading,
+            &opts.break_before_headings,
+            &opts.max_output_lines,
+            &opts.tags,
+        ];
+        for option in options {
+            h.write_str(&format!("{:?}", option));
+        }
+        h.write_str(context);
+        h.write_str(assets.link());
+        CellCache {
+            dir,
+            salt: h.0,
+            assets,
+        }
+    }
+
+    fn path(&self, fingerprint: u64) -> PathBuf {
+        let mut h = Fnv(self.salt);
+        h.write(&fingerprint.to_le_bytes());
+        self.dir.join(format!("{:016x}.typ", h.0))
+    }
+
+    /// The markup of the cell with `fingerprint`, if it was cached and its assets still exist.
+    pub fn get(&self, fingerprint: u64) -> Option<String> {
+        let path = self.path(fingerprint);
+        let entry = fs::read_to_string(&path).ok()?;
+        let (header, markup) = entry.split_once('\n')?;
+        let assets = header.strip_prefix(ASSETS_HEADER)?;
+        if !assets.split_whitespace().all(|a| self.assets.exists(a)) {
+            return None;
+        }
+        // Mark the entry as used, so that it isn't evicted.
+        let touched = fs::File::options()
+            .append(true)
+            .open(&path)
+            .and_then(|f| f.set_modified(SystemTime::now()));
+        if let Err(e) = touched {
+            debug!("cache: could not touch {}: {}", path.display(), e);
+        }
+        Some(markup.to_string())
+    }
+
+    /// Store the `markup` of the cell with `fingerprint`, which refers to `assets`. Failing to
+    /// write the cache only costs time later, so errors are merely logged.
+    pub fn put(&self, fingerprint: u64, markup: &str, assets: &[String]) {
+        let path = self.path(fingerprint);
+        let entry = format!("{} {}\n{}", ASSETS_HEADER, assets.join(" "), markup);
+        // Write and rename, so that concurrent runs never read a partial entry.
+        let temp = path.with_extension(format!(
+            "{}.tmp",
+            TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let written = fs::create_dir_all(&self.dir)
+            .and_then(|()| fs::write(&temp, entry))
+            .and_then(|()| fs::rename(&temp, &path));
+        if let Err(e) = written {
+            debug!("cache: could not write {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// Remove the entries in `dir` that weren't used for `MAX_AGE`, and temporary files left behind
+/// by interrupted runs.
+fn evict(dir: &Path) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    let now = SystemTime::now();
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let ours = path.extension().map_or(false, |e| e == "typ" || e == "tmp");
+        let stale = entry
+            .metadata()
+            .and_then(|m| m.modified())
+            .map_or(false, |t| {
+                now.duration_since(t).map_or(false, |age| age > MAX_AGE)
+            });
+        if ours && stale {
+            match fs::remove_file(&path) {
+                Ok(()) => debug!("cache: removed stale {}", path.display()),
+                Err(e) => debug!("cache: could not remove {}: {}", path.display(), e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An empty directory for the test `name`.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "jupyter2typst-cache-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn keys() {
+        let dir = test_dir("keys");
+        let assets_dir = dir.join("assets").to_string_lossy().into_owned();
+        let assets = Assets::new(Some(&assets_dir), "nb.ipynb", "nb.typ");
+        let opts = Options::default();
+        let cache = CellCache::in_dir(dir.clone(), &opts, &assets, "python");
+        cache.put(1, "markup", &[]);
+        assert_eq!(cache.get(1).as_deref(), Some("markup"));
+        assert_eq!(cache.get(2), None);
+
+        // Changing an option or the context changes the key.
+        let changed = Options {
+            line_numbers: true,
+            ..Options::default()
+        };
+        assert_eq!(
+            CellCache::in_dir(dir.clone(), &changed, &assets, "python").get(1),
+            None
+        );
+        assert_eq!(
+            CellCache::in_dir(dir.clone(), &opts, &assets, "r").get(1),
+            None
+        );
+        assert!(CellCache::in_dir(dir.clone(), &opts, &assets, "python")
+            .get(1)
+            .is_some());
+
+        // Entries whose assets are gone aren't used.
+        cache.put(3, "#image(..)", &[format!("{}/missing.png", assets.link())]);
+        assert_eq!(cache.get(3), None);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn eviction() {
+        let dir = test_dir("eviction");
+        let old = SystemTime::now() - MAX_AGE - Duration::from_secs(60);
+        for name in ["stale.typ", "stale.typ.0.tmp", "fresh.typ", "other.txt"] {
+            fs::write(dir.join(name), "").unwrap();
+        }
+        for name in ["stale.typ", "stale.typ.0.tmp", "other.txt"] {
+            let file = fs::File::options()
+                .append(true)
+                .open(dir.join(name))
+                .unwrap();
+            file.set_modified(old).unwrap();
+        }
+        evict(&dir);
+        let mut left = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        left.sort();
+        assert_eq!(left, ["fresh.typ", "other.txt"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,263 @@
+//! Conversion of single cells, including tag actions and nbgrader handling.
+
+use crate::config::TagAction;
+use crate::document::Layout;
+use crate::document::{typst_string, write_typst_string};
+use crate::markdown::write_markdown_cell;
+use crate::notebook::{Cell, CellType};
+use crate::output::format_cell_result;
+use crate::render::Context;
+use crate::split;
+use crate::J2TError;
+
+use std::borrow::Cow;
+use std::fmt::{self, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NbgraderMode {
+    /// Replace solution regions with placeholders.
+    Student,
+    /// Render everything, highlighting graded cells.
+    Solution,
+}
+
+impl std::str::FromStr for NbgraderMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "student" => Ok(NbgraderMode::Student),
+            "solution" => Ok(NbgraderMode::Solution),
+            _ => Err(format!(
+                "unknown nbgrader mode '{}' (expected 'student' or 'solution')",
+                s
+            )),
+        }
+    }
+}
+
+/// How execution counts are shown next to code and result blocks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Prompts {
+    /// `In [3]:` and `Out[3]:`, like the classic Notebook.
+    InOut,
+    /// `[3]` next to both blocks.
+    Numbers,
+    None,
+}
+
+impl Prompts {
+    pub fn name(self) -> &'static str {
+        match self {
+            Prompts::InOut => "in-out",
+            Prompts::Numbers => "numbers",
+            Prompts::None => "none",
+        }
+    }
+}
+
+impl std::str::FromStr for Prompts {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        [Prompts::InOut, Prompts::Numbers, Prompts::None]
+            .into_iter()
+            .find(|p| p.name() == s)
+            .ok_or_else(|| {
+                format!(
+                    "unknown prompt style '{}' (expected 'in-out', 'numbers' or 'none')",
+                    s
+                )
+            })
+    }
+}
+
+/// Write the template's prompt for the block of `kind` ("in" or "out") that follows.
+fn write_prompt(ctx: &Context, kind: &str, prompt: &str, out: &mut dyn Write) -> fmt::Result {
+    if ctx.opts.prompts == Prompts::None {
+        return Ok(());
+    }
+    writeln!(
+        out,
+        "#prompt({}, {})",
+        typst_string(kind),
+        typst_string(prompt)
+    )
+}
+
+/// Returns the actions configured for the cell's tags.
+pub fn cell_tag_actions(ctx: &Context, cell: &Cell) -> Vec<TagAction> {
+    cell.tags()
+        .into_iter()
+        .filter_map(|t| ctx.opts.tags.get(t))
+        .copied()
+        .collect()
+}
+
+pub const NBGRADER_CODE_PLACEHOLDER: &str = "# YOUR CODE HERE";
+
+pub const NBGRADER_ANSWER_PLACEHOLDER: &str = "YOUR ANSWER HERE";
+
+/// Replace everything between `BEGIN SOLUTION` and `END SOLUTION` marker lines with
+/// `placeholder`, like nbgrader does when generating the student version. If the source contains
+/// no markers at all, the whole source is replaced.
+pub fn strip_solution_regions(source: &str, placeholder: &str) -> String {
+    if !source.contains("BEGIN SOLUTION") {
+        return format!("{}\n", placeholder);
+    }
+    let mut out = String::with_capacity(source.len());
+    let mut in_solution = false;
+    for line in source.split_inclusive('\n') {
+        if line.contains("BEGIN SOLUTION") {
+            in_solution = true;
+            let indent = &line[..line.len() - line.trim_start().len()];
+            out.push_str(indent);
+            out.push_str(placeholder);
+            out.push('\n');
+        } else if line.contains("END SOLUTION") {
+            in_solution = false;
+        } else if !in_solution {
+            out.push_str(line);
+        }
+    }
+    out
+}
+
+/// Returns the margin note with the cell's point value, if it is a graded nbgrader cell.
+pub fn nbgrader_points_note(ctx: &Context, cell: &Cell) -> String {
+    if ctx.opts.nbgrader.is_none() || !cell.metadata_flag(&["nbgrader", "grade"]) {
+        return String::new();
+    }
+    match cell
+        .metadata_value(&["nbgrader", "points"])
+        .and_then(|p| p.get::<f64>())
+    {
+        Some(points) => format!("#pointsnote({})\n", points),
+        None => String::new(),
+    }
+}
+
+/// Write a page break into `out`. In two-column layout, the columns are closed around it, as
+/// Typst doesn't allow page breaks inside of them.
+pub fn write_page_break(ctx: &Context, out: &mut dyn Write) -> fmt::Result {
+    // A weak break doesn't leave an empty page at the start of the document.
+    match ctx.opts.layout {
+        Layout::OneColumn => out.write_str("\n#pagebreak(weak: true)\n"),
+        Layout::TwoColumn => out.write_str("]\n#pagebreak(weak: true)\n#twocol[\n"),
+    }
+}
+
+/// Mark the start of a chapter for `--split-by-heading`. Like page breaks, chapters can't start
+/// inside the columns of two-column layout.
+pub fn write_chapter_break(ctx: &Context, out: &mut dyn Write) -> fmt::Result {
+    match ctx.opts.layout {
+        Layout::OneColumn => out.write_str(split::CHAPTER_MARKER),
+        Layout::TwoColumn => write!(out, "]\n{}#twocol[\n", split::CHAPTER_MARKER),
+    }
+}
+
+/// Write the Typst markup for `cell` into `out`.
+pub fn format_cell(ctx: &Context, cell: Cell, out: &mut dyn Write) -> Result<(), J2TError> {
+    let tag_actions = cell_tag_actions(ctx, &cell);
+    if tag_actions.contains(&TagAction::RemoveCell) {
+        return Ok(());
+    }
+    if tag_actions.contains(&TagAction::PageBreak) {
+        write_page_break(ctx, out)?;
+    }
+    // Cells spanning the page interrupt the columns opened by `render_cells`.
+    if ctx.opts.layout == Layout::TwoColumn && tag_actions.contains(&TagAction::FullWidth) {
+        out.write_str("]\n#fullwidth[\n")?;
+        format_cell_body(ctx, cell, &tag_actions, out)?;
+        out.write_str("]\n#twocol[\n")?;
+        return Ok(());
+    }
+    format_cell_body(ctx, cell, &tag_actions, out)
+}
+
+fn format_cell_body(
+    ctx: &Context,
+    mut cell: Cell,
+    tag_actions: &[TagAction],
+    out: &mut dyn Write,
+) -> Result<(), J2TError> {
+    if cell.cell_type == CellType::Markdown {
+        *ctx.attachments.borrow_mut() = std::mem::take(&mut cell.attachments);
+        out.write_str(&nbgrader_points_note(ctx, &cell))?;
+        if ctx.opts.nbgrader == Some(NbgraderMode::Student)
+            && cell.metadata_flag(&["nbgrader", "solution"])
+        {
+            let stripped = strip_solution_regions(&cell.source, NBGRADER_ANSWER_PLACEHOLDER);
+            return write_markdown_cell(ctx, &stripped, out);
+        }
+        write_markdown_cell(ctx, &cell.source, out)
+    } else if cell.cell_type == CellType::Code {
+        let exec_count = cell.execution_count;
+        let prompt = exec_count
+            .map(|n| n.to_string())
+            .unwrap_or_else(|| " ".to_string());
+        let is_solution = cell.metadata_flag(&["nbgrader", "solution"]);
+        let code = if ctx.opts.nbgrader == Some(NbgraderMode::Student) && is_solution {
+            Cow::Owned(strip_solution_regions(
+                &cell.source,
+                NBGRADER_CODE_PLACEHOLDER,
+            ))
+        } else {
+            Cow::Borrowed(cell.source.as_str())
+        };
+        // JupyterLab stores the collapse state under `metadata.jupyter`; classic Notebook uses
+        // `metadata.collapsed` for the outputs.
+        let source_hidden = tag_actions.contains(&TagAction::RemoveInput)
+            || (ctx.opts.respect_collapse && cell.metadata_flag(&["jupyter", "source_hidden"]));
+        let outputs_hidden = tag_actions.contains(&TagAction::RemoveOutput)
+            || (ctx.opts.respect_collapse
+                && (cell.metadata_flag(&["jupyter", "outputs_hidden"])
+                    || cell.metadata_flag(&["collapsed"])));
+
+        let graded = ctx.opts.nbgrader == Some(NbgraderMode::Solution)
+            && (is_solution || cell.metadata_flag(&["nbgrader", "grade"]));
+
+        write!(out, "\n{}", nbgrader_points_note(ctx, &cell))?;
+        if !source_hidden {
+            write_prompt(ctx, "in", &prompt, out)?;
+            write!(
+                out,
+                "#codeblock(lang: {}, {}{}{}",
+                typst_string(&ctx.lang),
+                if graded {
+                    "bgcolor: bgcolor_graded, "
+                } else {
+                    ""
+                },
+                // Only passed when enabled, so that custom templates needn't support it.
+                if ctx.opts.line_numbers {
+                    "line_numbers: true, "
+                } else {
+                    ""
+                },
+                ctx.opts.wrap_code.block_argument(),
+            )?;
+            write_typst_string(out, &ctx.opts.wrap_code.apply(&code))?;
+            out.write_str(")\n")?;
+        }
+        // The student version must not reveal the outputs of the reference solution.
+        let student_solution = ctx.opts.nbgrader == Some(NbgraderMode::Student) && is_solution;
+        if !(outputs_hidden
+            || student_solution
+            || (exec_count.is_none() && ctx.opts.skip_unexecuted))
+        {
+            write_prompt(ctx, "out", &prompt, out)?;
+            write!(
+                out,
+                "#resultblock({}",
+                ctx.opts.wrap_output.block_argument()
+            )?;
+            format_cell_result(ctx, &cell, out)?;
+            out.write_str(")\n")?;
+        }
+        out.write_str("\n")?;
+        Ok(())
+    } else {
+        Ok(())
+    }
+}
@@ -0,0 +1,218 @@
+//! Structural validation of notebooks for `--check`.
+
+use tinyjson::JsonValue;
+
+use std::collections::HashMap;
+use std::fmt;
+
+/// A problem found in a notebook, optionally located in a cell.
+#[derive(Debug)]
+pub struct Problem {
+    pub cell: Option<usize>,
+    pub msg: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.cell {
+            Some(cell) => write!(f, "cell {}: {}", cell, self.msg),
+            None => write!(f, "{}", self.msg),
+        }
+    }
+}
+
+const OUTPUT_TYPES: &[&str] = &["stream", "display_data", "execute_result", "error"];
+
+/// Collects problems while walking the notebook.
+struct Checker {
+    problems: Vec<Problem>,
+    cell: Option<usize>,
+}
+
+impl Checker {
+    fn report(&mut self, msg: String) {
+        self.problems.push(Problem {
+            cell: self.cell,
+            msg,
+        });
+    }
+
+    /// Check that `obj[key]` exists and satisfies `valid`.
+    fn require(
+        &mut self,
+        obj: &HashMap<String, JsonValue>,
+        key: &str,
+        what: &str,
+        valid: fn(&JsonValue) -> bool,
+    ) {
+        match obj.get(key) {
+            None => self.report(format!("missing key `{}`", key)),
+            Some(v) if !valid(v) => self.report(format!("`{}` must be {}", key, what)),
+            Some(_) => (),
+        }
+    }
+}
+
+/// Sources and texts are either a string or a list of strings.
+fn is_multiline_string(v: &JsonValue) -> bool {
+    match v {
+        JsonValue::String(_) => true,
+        JsonValue::Array(a) => a.iter().all(JsonValue::is_string),
+        _ => false,
+    }
+}
+
+fn is_execution_count(v: &JsonValue) -> bool {
+    v.is_number() || v.is_null()
+}
+
+fn multiline_string(v: &JsonValue) -> String {
+    match v {
+        JsonValue::String(s) => s.clone(),
+        JsonValue::Array(a) => a
+            .iter()
+            .filter_map(|s| s.get::<String>())
+            .cloned()
+            .collect(),
+        _ => String::new(),
+    }
+}
+
+/// Names referenced as `attachment:<name>` in Markdown source.
+fn referenced_attachments(source: &str) -> Vec<&str> {
+    source
+        .match_indices("attachment:")
+        .map(|(i, prefix)| {
+            let name = &source[i + prefix.len()..];
+            let end = name
+                .find(|c: char| c == ')' || c == '"' || c.is_whitespace())
+                .unwrap_or(name.len());
+            &name[..end]
+        })
+        .collect()
+}
+
+/// Validate the structure of the notebook `nb` against nbformat 4. Legacy notebooks are checked
+/// after upgrading them.
+pub fn validate(nb: &JsonValue) -> Vec<Problem> {
+    let mut checker = Checker {
+        problems: vec![],
+        cell: None,
+    };
+    let nb = match nb {
+        JsonValue::Object(nb) => nb,
+        _ => {
+            checker.report("notebook is not a JSON object".to_string());
+            return checker.problems;
+        }
+    };
+
+    match nb.get("nbformat").and_then(|v| v.get::<f64>()) {
+        Some(v) if *v == 4.0 => (),
+        Some(v) => checker.report(format!("unsupported nbformat version {}", v)),
+        None => checker.report("missing key `nbformat`".to_string()),
+    }
+    checker.require(nb, "metadata", "an object", JsonValue::is_object);
+    checker.require(nb, "cells", "a list", JsonValue::is_array);
+
+    let cells = match nb.get("cells").and_then(|c| c.get::<Vec<JsonValue>>()) {
+        Some(cells) => cells,
+        None => return checker.problems,
+    };
+    for (i, cell) in cells.iter().enumerate() {
+        checker.cell = Some(i);
+        match cell {
+            JsonValue::Object(cell) => validate_cell(&mut checker, cell),
+            _ => checker.report("cell is not a JSON object".to_string()),
+        }
+    }
+    checker.problems
+}
+
+fn validate_cell(checker: &mut Checker, cell: &HashMap<String, JsonValue>) {
+    checker.require(
+        cell,
+        "source",
+        "a string or list of strings",
+        is_multiline_string,
+    );
+    checker.require(cell, "metadata", "an object", JsonValue::is_object);
+
+    match cell.get("cell_type").and_then(|t| t.get::<String>()) {
+        Some(t) if t == "code" => {
+            checker.require(
+                cell,
+                "execution_count",
+                "a number or null",
+                is_execution_count,
+            );
+            checker.require(cell, "outputs", "a list", JsonValue::is_array);
+            if let Some(outputs) = cell.get("outputs").and_then(|o| o.get::<Vec<JsonValue>>()) {
+                for (j, output) in outputs.iter().enumerate() {
+                    validate_output(checker, j, output);
+                }
+            }
+        }
+        Some(t) if t == "markdown" => {
+            let source = cell.get("source").map(multiline_string).unwrap_or_default();
+            let attachments = cell
+                .get("attachments")
+                .and_then(|a| a.get::<HashMap<String, JsonValue>>());
+            for name in referenced_attachments(&source) {
+                if !attachments.map_or(false, |a| a.contains_key(name)) {
+                    checker.report(format!("attachment `{}` is referenced but missing", name));
+                }
+            }
+        }
+        Some(t) if t == "raw" => (),
+        Some(t) => checker.report(format!("unknown cell type `{}`", t)),
+        None => checker.report("missing key `cell_type`".to_string()),
+    }
+}
+
+fn validate_output(checker: &mut Checker, index: usize, output: &JsonValue) {
+    let output = match output {
+        JsonValue::Object(o) => o,
+        _ => {
+            checker.report(format!("output {} is not a JSON object", index));
+            return;
+        }
+    };
+    let problems_before = checker.problems.len();
+    match output.get("output_type").and_then(|t| t.get::<String>()) {
+        Some(t) if t == "stream" => {
+            checker.require(output, "name", "a string", JsonValue::is_string);
+            checker.require(
+                output,
+                "text",
+                "a string or list of strings",
+                is_multiline_string,
+            );
+        }
+        Some(t) if t == "display_data" || t == "execute_result" => {
+            checker.require(output, "data", "an object", JsonValue::is_object);
+            if t == "execute_result" {
+                checker.require(
+                    output,
+                    "execution_count",
+                    "a number or null",
+                    is_execution_count,
+                );
+            }
+        }
+        Some(t) if t == "error" => {
+            checker.require(output, "ename", "a string", JsonValue::is_string);
+            checker.require(output, "evalue", "a string", JsonValue::is_string);
+            checker.require(output, "traceback", "a list", JsonValue::is_array);
+        }
+        Some(t) => checker.report(format!(
+            "unknown output type `{}` (expected one of {})",
+            t,
+            OUTPUT_TYPES.join(", ")
+        )),
+        None => checker.report("missing key `output_type`".to_string()),
+    }
+    for problem in checker.problems[problems_before..].iter_mut() {
+        problem.msg = format!("output {}: {}", index, problem.msg);
+    }
+}
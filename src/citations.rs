@@ -0,0 +1,138 @@
+//! Pandoc-style citations in Markdown text: `[@key]`, `[see @key, p. 3; -@other]`.
+//!
+//! Each citation in brackets becomes a `#cite` call. A locator after the key becomes the
+//! supplement, and `-@key` (suppressing the author) uses the `year` form. Bare `@key` is already
+//! a reference in Typst and is left alone.
+
+use crate::document::{needs_call_terminator, typst_string};
+
+use std::fmt::{self, Write};
+
+struct Citation<'a> {
+    prefix: &'a str,
+    key: &'a str,
+    locator: &'a str,
+    suppress_author: bool,
+}
+
+/// Write the Markdown text `text` into `out`, turning bracketed citations into `#cite` calls.
+pub fn write_with_citations(out: &mut dyn Write, text: &str) -> fmt::Result {
+    let mut rest = text;
+    while let Some(start) = rest.find('[') {
+        let group = rest[start + 1..].find(']').and_then(|len| {
+            let citations = parse_group(&rest[start + 1..start + 1 + len])?;
+            Some((citations, start + len + 2))
+        });
+        match group {
+            Some((citations, end)) => {
+                out.write_str(&rest[..start])?;
+                write_citations(out, &citations)?;
+                rest = &rest[end..];
+                if needs_call_terminator(rest) {
+                    out.write_char(';')?;
+                }
+            }
+            None => {
+                out.write_str(&rest[..=start])?;
+                rest = &rest[start + 1..];
+            }
+        }
+    }
+    out.write_str(rest)
+}
+
+/// Parse the text between brackets as `;`-separated citations, if it is one.
+fn parse_group(s: &str) -> Option<Vec<Citation<'_>>> {
+    s.split(';').map(parse_citation).collect()
+}
+
+fn parse_citation(s: &str) -> Option<Citation<'_>> {
+    // As in Pandoc, the key starts the item or follows a space, possibly after the `-`
+    // suppressing the author, so that `[mail foo@bar.com]` is no citation.
+    let at = s.match_indices('@').map(|(i, _)| i).find(|&i| {
+        let before = &s[..i];
+        let before = before.strip_suffix('-').unwrap_or(before);
+        before.is_empty() || before.ends_with(char::is_whitespace)
+    })?;
+    let (prefix, rest) = s.split_at(at);
+    let (prefix, suppress_author) = match prefix.strip_suffix('-') {
+        Some(prefix) => (prefix, true),
+        None => (prefix, false),
+    };
+    let rest = &rest[1..];
+    let end = rest.find(|c: char| !is_key_char(c)).unwrap_or(rest.len());
+    // Keys may contain punctuation, but not at the end: `@doe99,` is `doe99`.
+    let key = rest[..end].trim_end_matches(|c: char| !c.is_alphanumeric() && c != '_');
+    if key.is_empty() {
+        return None;
+    }
+    Some(Citation {
+        prefix: prefix.trim(),
+        key,
+        locator: rest[key.len()..].trim_start_matches(',').trim(),
+        suppress_author,
+    })
+}
+
+fn is_key_char(c: char) -> bool {
+    c.is_alphanumeric() || "_:.#$%&-+?<>~/".contains(c)
+}
+
+fn write_citations(out: &mut dyn Write, citations: &[Citation]) -> fmt::Result {
+    for (i, citation) in citations.iter().enumerate() {
+        // Citations separated only by spaces are grouped by Typst.
+        if i > 0 {
+            out.write_char(' ')?;
+        }
+        if !citation.prefix.is_empty() {
+            write!(out, "{} ", citation.prefix)?;
+        }
+        write!(out, "#cite({}", typst_label(citation.key))?;
+        if citation.suppress_author {
+            out.write_str(", form: \"year\"")?;
+        }
+        if !citation.locator.is_empty() {
+            write!(out, ", supplement: [{}]", citation.locator)?;
+        }
+        out.write_char(')')?;
+    }
+    Ok(())
+}
+
+/// The label `<key>`, or `label("key")` for keys with characters not allowed in label syntax.
+fn typst_label(key: &str) -> String {
+    if key
+        .chars()
+        .all(|c| c.is_alphanumeric() || "_-.:".contains(c))
+    {
+        format!("<{}>", key)
+    } else {
+        format!("label({})", typst_string(key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn convert(text: &str) -> String {
+        let mut out = String::new();
+        write_with_citations(&mut out, text).unwrap();
+        out
+    }
+
+    #[test]
+    fn citations() {
+        assert_eq!(convert("see [@doe99]."), "see #cite(<doe99>).");
+        assert_eq!(
+            convert("[-@doe99; see @roe, p. 3]"),
+            r#"#cite(<doe99>, form: "year") see #cite(<roe>, supplement: [p. 3])"#
+        );
+    }
+
+    #[test]
+    fn email_addresses() {
+        assert_eq!(convert("[mail foo@bar.com]"), "[mail foo@bar.com]");
+        assert_eq!(convert("[a-@b]"), "[a-@b]");
+    }
+}
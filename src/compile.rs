@@ -0,0 +1,131 @@
+//! Compilation of the generated Typst source into PDF or SVG using the typst library, so that no
+//! separate Typst installation is needed.
+
+use crate::{Date, J2TError};
+
+use comemo::Prehashed;
+use typst::diag::{FileError, FileResult};
+use typst::eval::Tracer;
+use typst::foundations::{Bytes, Datetime, Smart};
+use typst::layout::Abs;
+use typst::syntax::{FileId, Source, VirtualPath};
+use typst::text::{Font, FontBook};
+use typst::{Library, World};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Pdf,
+    /// All pages merged into one SVG image.
+    Svg,
+}
+
+impl Format {
+    /// Choose the format by file extension.
+    pub fn from_path(path: &Path) -> Option<Format> {
+        match path.extension()?.to_str()? {
+            "pdf" => Some(Format::Pdf),
+            "svg" => Some(Format::Svg),
+            _ => None,
+        }
+    }
+}
+
+/// The environment of a single compilation: the generated source as main file, files below
+/// `root` (extracted assets, images), and the fonts bundled with typst.
+struct NotebookWorld {
+    root: PathBuf,
+    main: Source,
+    library: Prehashed<Library>,
+    book: Prehashed<FontBook>,
+    fonts: Vec<Font>,
+}
+
+impl NotebookWorld {
+    fn new(root: &Path, main: &Path, source: String) -> NotebookWorld {
+        let fonts = typst_assets::fonts()
+            .flat_map(|data| Font::iter(Bytes::from_static(data)))
+            .collect::<Vec<_>>();
+        NotebookWorld {
+            root: root.to_path_buf(),
+            main: Source::new(FileId::new(None, VirtualPath::new(main)), source),
+            library: Prehashed::new(Library::builder().build()),
+            book: Prehashed::new(FontBook::from_fonts(&fonts)),
+            fonts,
+        }
+    }
+
+    fn read(&self, id: FileId) -> FileResult<Vec<u8>> {
+        let path = id
+            .vpath()
+            .resolve(&self.root)
+            .ok_or(FileError::AccessDenied)?;
+        fs::read(&path).map_err(|e| FileError::from_io(e, &path))
+    }
+}
+
+impl World for NotebookWorld {
+    fn library(&self) -> &Prehashed<Library> {
+        &self.library
+    }
+
+    fn book(&self) -> &Prehashed<FontBook> {
+        &self.book
+    }
+
+    fn main(&self) -> Source {
+        self.main.clone()
+    }
+
+    fn source(&self, id: FileId) -> FileResult<Source> {
+        if id == self.main.id() {
+            return Ok(self.main.clone());
+        }
+        let text = String::from_utf8(self.read(id)?).map_err(|_| FileError::InvalidUtf8)?;
+        Ok(Source::new(id, text))
+    }
+
+    fn file(&self, id: FileId) -> FileResult<Bytes> {
+        self.read(id).map(Bytes::from)
+    }
+
+    fn font(&self, index: usize) -> Option<Font> {
+        self.fonts.get(index).cloned()
+    }
+
+    fn today(&self, _offset: Option<i64>) -> Option<Datetime> {
+        let today = Date::from_system_time(SystemTime::now())?;
+        Datetime::from_ymd(today.year as i32, today.month as u8, today.day as u8)
+    }
+}
+
+/// Compile `source` into a document of the given format. `main` is the path of the source in
+/// `root`, which relative paths in the source are resolved against. Files outside of `root` can't
+/// be read.
+pub fn compile(
+    source: String,
+    root: &Path,
+    main: &Path,
+    format: Format,
+) -> Result<Vec<u8>, J2TError> {
+    let world = NotebookWorld::new(root, main, source);
+    let mut tracer = Tracer::new();
+    let document = typst::compile(&world, &mut tracer).map_err(|errors| J2TError {
+        msg: Some(format!(
+            "typst compilation failed: {}",
+            errors
+                .iter()
+                .map(|e| e.message.to_string())
+                .collect::<Vec<_>>()
+                .join("; ")
+        )),
+        ..Default::default()
+    })?;
+    Ok(match format {
+        Format::Pdf => typst_pdf::pdf(&document, Smart::Auto, None),
+        Format::Svg => typst_svg::svg_merged(&document, Abs::pt(10.0)).into_bytes(),
+    })
+}
@@ -0,0 +1,619 @@
+//! Conversion options. Defaults can be set in a `jupyter2typst.toml` file, which in turn is
+//! overridden by command line flags. Keys are named like the flags:
+//!
+//! ```toml
+//! theme = "report"
+//! template = "custom.typ"
+//! toc = true
+//! mime_priority = ["image/png", "text/plain"]
+//! max_output_lines = 40
+//! break_before_headings = [1]
+//! assets_dir = "assets"
+//! bibliography = "references.bib"
+//!
+//! [page]
+//! paper = "us-letter"
+//! font_size = "10pt"
+//!
+//! [style]
+//! code_bg = "#f4f4f4"
+//! block_radius = "0pt"
+//!
+//! [tags]
+//! solution = "remove-cell"
+//!
+//! [lang_map]
+//! sagemath = "python"
+//! ```
+
+use crate::{
+    is_remote, typst_color, typst_length, typst_stroke, BlockStyle, J2TError, Layout, NbgraderMode,
+    PageSetup, Prompts, Wrap,
+};
+
+use tinyjson::JsonValue;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Keys that clients of `--serve` may set. They only affect rendering; keys naming files, which
+/// would let any client read or write files of the server's user, are left out.
+pub const REQUEST_KEYS: &[&str] = &[
+    "skip_unexecuted",
+    "respect_collapse",
+    "prompts",
+    "line_numbers",
+    "wrap_code",
+    "wrap_output",
+    "nbgrader",
+    "lang",
+    "lang_map",
+    "theme",
+    "toc",
+    "toc_depth",
+    "pdf",
+    "strict",
+    "mime_priority",
+    "layout",
+    "smartquotes",
+    "emoji_font",
+    "tableize_output",
+    "break_before_headings",
+    "max_output_lines",
+    "page",
+    "style",
+    "tags",
+];
+
+/// Name of the configuration file looked up next to the notebook.
+pub const CONFIG_FILE_NAME: &str = "jupyter2typst.toml";
+
+/// What to do with a cell carrying a certain tag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TagAction {
+    /// Drop the cell entirely.
+    RemoveCell,
+    /// Drop the cell's source code.
+    RemoveInput,
+    /// Drop the cell's outputs.
+    RemoveOutput,
+    /// Start a new page before the cell.
+    PageBreak,
+    /// Span the whole page width in two-column layout. The columns are closed before the cell
+    /// and opened again after it, without being balanced.
+    FullWidth,
+}
+
+impl std::str::FromStr for TagAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "remove-cell" => Ok(TagAction::RemoveCell),
+            "remove-input" => Ok(TagAction::RemoveInput),
+            "remove-output" => Ok(TagAction::RemoveOutput),
+            "pagebreak" => Ok(TagAction::PageBreak),
+            "fullwidth" => Ok(TagAction::FullWidth),
+            _ => Err(format!(
+                "unknown tag action '{}' (expected remove-cell, remove-input, remove-output, \
+                 pagebreak or fullwidth)",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Options {
+    /// Report details about the conversion, like `-v`.
+    pub verbose: bool,
+    /// Omit the result block of cells that were never executed.
+    pub skip_unexecuted: bool,
+    /// Hide code and outputs that were collapsed in the notebook UI.
+    pub respect_collapse: bool,
+    /// How execution counts are shown.
+    pub prompts: Prompts,
+    /// Number the lines of each code cell.
+    pub line_numbers: bool,
+    /// Handling of long lines in code cells.
+    pub wrap_code: Wrap,
+    /// Handling of long lines in text outputs.
+    pub wrap_output: Wrap,
+    /// Render an nbgrader assignment as student or solution version.
+    pub nbgrader: Option<NbgraderMode>,
+    /// Language of code cells, overriding the kernel language from the notebook metadata.
+    pub lang: Option<String>,
+    pub theme: String,
+    /// Template file replacing the built-in definitions.
+    pub template: Option<String>,
+    pub page: PageSetup,
+    /// Overrides for the theme's block styling.
+    pub style: BlockStyle,
+    pub toc: bool,
+    pub toc_depth: usize,
+    /// MIME types of output data to render, most preferred first.
+    pub mime_priority: Vec<String>,
+    /// Page layout of the cells.
+    pub layout: Layout,
+    /// Use typographic quotes, dashes and ellipses in Markdown text.
+    pub smartquotes: bool,
+    /// Font for emoji in Markdown text, if the text font lacks them.
+    pub emoji_font: Option<String>,
+    /// Show `text/plain` outputs that look like DataFrames as tables.
+    pub tableize_output: bool,
+    /// Write a chapter file for each Markdown heading up to this level, included by the output.
+    pub split_by_heading: Option<usize>,
+    /// Levels of Markdown headings to start a new page before.
+    pub break_before_headings: Vec<usize>,
+    /// Truncate outputs longer than this many lines.
+    pub max_output_lines: Option<usize>,
+    /// Directory for extracted images and attachments (default: `<outfile>_assets`).
+    pub assets_dir: Option<String>,
+    /// BibTeX or Hayagriva file with the works cited in Markdown cells.
+    pub bibliography: Option<String>,
+    /// JSON file with LaTeX macros used in math, in addition to those in the notebook.
+    pub math_macros: Option<String>,
+    /// Typst `raw` languages by kernel language, in addition to the built-in ones.
+    pub lang_map: BTreeMap<String, String>,
+    /// Actions applied to cells by tag.
+    pub tags: BTreeMap<String, TagAction>,
+    /// Reuse cells converted by earlier runs from `.jupyter2typst-cache`. Options read while
+    /// converting cells must be part of the cache key in `CellCache::new`.
+    pub cache: bool,
+    /// Compile the generated source into PDF or SVG instead of writing it out.
+    pub compile: bool,
+    /// Abort on content that can't be converted, instead of skipping it with a warning.
+    pub strict: bool,
+    /// Token for notebooks on a Jupyter Server. Not read from configuration files.
+    pub token: Option<String>,
+    /// Shell command each cell's JSON is passed through before conversion. Like the post filter,
+    /// it can't be set in configuration files, which could otherwise run arbitrary commands.
+    pub cell_filter: Option<String>,
+    /// Shell command the generated Typst source is passed through.
+    pub post_filter: Option<String>,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        // The tag names used by Jupyter Book for the same purpose.
+        let tags = [
+            ("remove-cell", TagAction::RemoveCell),
+            ("remove-input", TagAction::RemoveInput),
+            ("remove-output", TagAction::RemoveOutput),
+            ("pagebreak", TagAction::PageBreak),
+            ("fullwidth", TagAction::FullWidth),
+        ]
+        .into_iter()
+        .map(|(tag, action)| (tag.to_string(), action))
+        .collect();
+        Options {
+            verbose: false,
+            skip_unexecuted: false,
+            respect_collapse: false,
+            prompts: Prompts::Numbers,
+            line_numbers: false,
+            wrap_code: Wrap::Off,
+            wrap_output: Wrap::Off,
+            nbgrader: None,
+            lang: None,
+            theme: "default".to_string(),
+            template: None,
+            page: PageSetup::default(),
+            style: BlockStyle::default(),
+            toc: false,
+            toc_depth: 3,
+            mime_priority: [
+                "image/svg+xml",
+                "image/png",
+                "image/jpeg",
+                "text/markdown",
+                "text/plain",
+            ]
+            .iter()
+            .map(|m| m.to_string())
+            .collect(),
+            max_output_lines: None,
+            layout: Layout::OneColumn,
+            tableize_output: false,
+            smartquotes: false,
+            emoji_font: None,
+            split_by_heading: None,
+            break_before_headings: vec![],
+            assets_dir: None,
+            bibliography: None,
+            math_macros: None,
+            lang_map: BTreeMap::new(),
+            tags,
+            cache: false,
+            compile: false,
+            strict: false,
+            token: None,
+            cell_filter: None,
+            post_filter: None,
+        }
+    }
+}
+
+fn config_error(path: &Path, msg: String) -> J2TError {
+    J2TError {
+        msg: Some(format!("{}: {}", path.display(), msg)),
+        ..Default::default()
+    }
+}
+
+fn string_value(path: &Path, key: &str, value: &toml::Value) -> Result<String, J2TError> {
+    value
+        .as_str()
+        .map(str::to_string)
+        .ok_or_else(|| config_error(path, format!("`{}` must be a string", key)))
+}
+
+fn bool_value(path: &Path, key: &str, value: &toml::Value) -> Result<bool, J2TError> {
+    value
+        .as_bool()
+        .ok_or_else(|| config_error(path, format!("`{}` must be a boolean", key)))
+}
+
+fn usize_value(path: &Path, key: &str, value: &toml::Value) -> Result<usize, J2TError> {
+    value
+        .as_integer()
+        .and_then(|i| usize::try_from(i).ok())
+        .ok_or_else(|| config_error(path, format!("`{}` must be a positive integer", key)))
+}
+
+fn wrap_value(path: &Path, key: &str, value: &toml::Value) -> Result<Wrap, J2TError> {
+    string_value(path, key, value)?
+        .parse()
+        .map_err(|e| config_error(path, e))
+}
+
+fn length_value(path: &Path, key: &str, value: &toml::Value) -> Result<String, J2TError> {
+    typst_length(&string_value(path, key, value)?).map_err(|e| config_error(path, e))
+}
+
+fn color_value(path: &Path, key: &str, value: &toml::Value) -> Result<String, J2TError> {
+    typst_color(&string_value(path, key, value)?).map_err(|e| config_error(path, e))
+}
+
+fn stroke_value(path: &Path, key: &str, value: &toml::Value) -> Result<String, J2TError> {
+    typst_stroke(&string_value(path, key, value)?).map_err(|e| config_error(path, e))
+}
+
+impl Options {
+    /// Load options from the file `config`, or from a `jupyter2typst.toml` next to `infile` if it
+    /// exists. Without either, the defaults are returned.
+    pub fn load(config: Option<&str>, infile: &str) -> Result<Options, J2TError> {
+        let mut opts = Options::default();
+        if let Some(path) = Options::config_path(config, infile) {
+            opts.apply_file(&path)?;
+        }
+        Ok(opts)
+    }
+
+    /// The configuration file used for `infile`: `config` if given, otherwise a
+    /// `jupyter2typst.toml` next to the notebook if there is one.
+    pub fn config_path(config: Option<&str>, infile: &str) -> Option<PathBuf> {
+        match config {
+            Some(config) => Some(PathBuf::from(config)),
+            None => {
+                // Downloaded notebooks use the configuration in the working directory.
+                let dir = if is_remote(infile) {
+                    Path::new("")
+                } else {
+                    Path::new(infile).parent().unwrap_or(Path::new(""))
+                };
+                let candidate = dir.join(CONFIG_FILE_NAME);
+                candidate.is_file().then_some(candidate)
+            }
+        }
+    }
+
+    fn apply_file(&mut self, path: &Path) -> Result<(), J2TError> {
+        let table = fs::read_to_string(path)?
+            .parse::<toml::Table>()
+            .map_err(|e| config_error(path, e.to_string()))?;
+        self.apply_table(path, &table)
+    }
+
+    /// Apply the options in the JSON object `json`, which has the keys of the configuration file.
+    /// `name` is used like the path of a configuration file.
+    pub fn apply_json(&mut self, name: &Path, json: JsonValue) -> Result<(), J2TError> {
+        match toml_value(json) {
+            Some(toml::Value::Table(table)) => self.apply_table(name, &table),
+            _ => Err(config_error(name, "options must be an object".to_string())),
+        }
+    }
+
+    /// Apply the options in the JSON object `json`, sent by a client of `--serve`. Only
+    /// `REQUEST_KEYS` are accepted.
+    pub fn apply_request_json(&mut self, json: JsonValue) -> Result<(), J2TError> {
+        let name = Path::new("options");
+        let table = match toml_value(json) {
+            Some(toml::Value::Table(table)) => table,
+            _ => return Err(config_error(name, "options must be an object".to_string())),
+        };
+        if let Some(key) = table
+            .keys()
+            .find(|key| !REQUEST_KEYS.contains(&key.as_str()))
+        {
+            return Err(config_error(
+                name,
+                format!("`{}` can't be set in requests", key),
+            ));
+        }
+        self.apply_table(name, &table)
+    }
+
+    /// Apply the options in `table`, read from `path`, which is named in errors. Paths in the
+    /// table are relative to the directory of `path`.
+    pub fn apply_table(&mut self, path: &Path, table: &toml::Table) -> Result<(), J2TError> {
+        let base = path.parent().unwrap_or(Path::new(""));
+
+        for (key, value) in table.iter() {
+            match key.as_str() {
+                "verbose" => self.verbose = bool_value(path, key, value)?,
+                "skip_unexecuted" => self.skip_unexecuted = bool_value(path, key, value)?,
+                "respect_collapse" => self.respect_collapse = bool_value(path, key, value)?,
+                "prompts" => {
+                    self.prompts = string_value(path, key, value)?
+                        .parse()
+                        .map_err(|e| config_error(path, e))?
+                }
+                "line_numbers" => self.line_numbers = bool_value(path, key, value)?,
+                "wrap_code" => self.wrap_code = wrap_value(path, key, value)?,
+                "wrap_output" => self.wrap_output = wrap_value(path, key, value)?,
+                "nbgrader" => {
+                    self.nbgrader = Some(
+                        string_value(path, key, value)?
+                            .parse()
+                            .map_err(|e| config_error(path, e))?,
+                    )
+                }
+                "lang" => self.lang = Some(string_value(path, key, value)?),
+                "theme" => self.theme = string_value(path, key, value)?,
+                "template" => {
+                    let template = base.join(string_value(path, key, value)?);
+                    self.template = Some(template.to_string_lossy().into_owned());
+                }
+                "toc" => self.toc = bool_value(path, key, value)?,
+                "pdf" => self.compile = bool_value(path, key, value)?,
+                "strict" => self.strict = bool_value(path, key, value)?,
+                "cache" => self.cache = bool_value(path, key, value)?,
+                "toc_depth" => self.toc_depth = usize_value(path, key, value)?,
+                "mime_priority" => {
+                    self.mime_priority = value
+                        .as_array()
+                        .map(|a| a.iter().map(|m| string_value(path, key, m)).collect())
+                        .unwrap_or_else(|| {
+                            Err(config_error(path, format!("`{}` must be an array", key)))
+                        })?
+                }
+                "layout" => {
+                    self.layout = string_value(path, key, value)?
+                        .parse()
+                        .map_err(|e| config_error(path, e))?
+                }
+                "smartquotes" => self.smartquotes = bool_value(path, key, value)?,
+                "emoji_font" => self.emoji_font = Some(string_value(path, key, value)?),
+                "tableize_output" => self.tableize_output = bool_value(path, key, value)?,
+                "split_by_heading" => self.split_by_heading = Some(usize_value(path, key, value)?),
+                "break_before_headings" => {
+                    self.break_before_headings = value
+                        .as_array()
+                        .map(|a| a.iter().map(|l| usize_value(path, key, l)).collect())
+                        .unwrap_or_else(|| {
+                            Err(config_error(path, format!("`{}` must be an array", key)))
+                        })?
+                }
+                "max_output_lines" => self.max_output_lines = Some(usize_value(path, key, value)?),
+                "assets_dir" => {
+                    let dir = base.join(string_value(path, key, value)?);
+                    self.assets_dir = Some(dir.to_string_lossy().into_owned());
+                }
+                "bibliography" => {
+                    let bibliography = base.join(string_value(path, key, value)?);
+                    self.bibliography = Some(bibliography.to_string_lossy().into_owned());
+                }
+                "math_macros" => {
+                    let macros = base.join(string_value(path, key, value)?);
+                    self.math_macros = Some(macros.to_string_lossy().into_owned());
+                }
+                "page" => self.apply_page_table(path, value)?,
+                "style" => self.apply_style_table(path, value)?,
+                "tags" => {
+                    let tags = value
+                        .as_table()
+                        .ok_or_else(|| config_error(path, "`tags` must be a table".to_string()))?;
+                    for (tag, action) in tags.iter() {
+                        let action = string_value(path, tag, action)?
+                            .parse()
+                            .map_err(|e| config_error(path, e))?;
+                        self.tags.insert(tag.clone(), action);
+                    }
+                }
+                "lang_map" => {
+                    let map = value.as_table().ok_or_else(|| {
+                        config_error(path, "`lang_map` must be a table".to_string())
+                    })?;
+                    for (kernel, lang) in map.iter() {
+                        self.lang_map
+                            .insert(kernel.to_lowercase(), string_value(path, kernel, lang)?);
+                    }
+                }
+                _ => return Err(config_error(path, format!("unknown key `{}`", key))),
+            }
+        }
+        Ok(())
+    }
+
+    fn apply_page_table(&mut self, path: &Path, value: &toml::Value) -> Result<(), J2TError> {
+        let page = value
+            .as_table()
+            .ok_or_else(|| config_error(path, "`page` must be a table".to_string()))?;
+        for (key, value) in page.iter() {
+            match key.as_str() {
+                "paper" => self.page.paper = string_value(path, key, value)?,
+                "margin" => self.page.margin = Some(length_value(path, key, value)?),
+                "font" => self.page.font = Some(string_value(path, key, value)?),
+                "font_size" => self.page.font_size = Some(length_value(path, key, value)?),
+                "code_font_size" => {
+                    self.page.code_font_size = Some(length_value(path, key, value)?)
+                }
+                _ => return Err(config_error(path, format!("unknown key `page.{}`", key))),
+            }
+        }
+        Ok(())
+    }
+
+    fn apply_style_table(&mut self, path: &Path, value: &toml::Value) -> Result<(), J2TError> {
+        let style = value
+            .as_table()
+            .ok_or_else(|| config_error(path, "`style` must be a table".to_string()))?;
+        for (key, value) in style.iter() {
+            match key.as_str() {
+                "code_bg" => self.style.code_bg = Some(color_value(path, key, value)?),
+                "result_bg" => self.style.result_bg = Some(color_value(path, key, value)?),
+                "result_stroke" => self.style.result_stroke = Some(stroke_value(path, key, value)?),
+                "block_radius" => self.style.block_radius = Some(length_value(path, key, value)?),
+                _ => return Err(config_error(path, format!("unknown key `style.{}`", key))),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The value of a flag set to `config` in the configuration file, and turned on by `--x` (`on`)
+/// or off by `--no-x` (`off`) on the command line.
+pub fn flag(config: bool, on: bool, off: bool) -> bool {
+    if off {
+        false
+    } else {
+        config || on
+    }
+}
+
+/// Convert JSON into the equivalent TOML value, dropping `null`s.
+fn toml_value(json: JsonValue) -> Option<toml::Value> {
+    Some(match json {
+        JsonValue::Null => return None,
+        JsonValue::Boolean(b) => toml::Value::Boolean(b),
+        JsonValue::Number(n) if n.fract() == 0.0 => toml::Value::Integer(n as i64),
+        JsonValue::Number(n) => toml::Value::Float(n),
+        JsonValue::String(s) => toml::Value::String(s),
+        JsonValue::Array(a) => toml::Value::Array(a.into_iter().filter_map(toml_value).collect()),
+        JsonValue::Object(o) => toml::Value::Table(
+            o.into_iter()
+                .filter_map(|(k, v)| Some((k, toml_value(v)?)))
+                .collect(),
+        ),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    fn parse(source: &str) -> Result<Options, J2TError> {
+        let table = source.parse::<toml::Table>().unwrap();
+        let mut opts = Options::default();
+        opts.apply_table(Path::new("dir/jupyter2typst.toml"), &table)?;
+        Ok(opts)
+    }
+
+    fn error(source: &str) -> String {
+        parse(source).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn keys() {
+        let opts = parse(
+            r##"
+theme = "report"
+template = "custom.typ"
+pdf = true
+toc_depth = 2
+mime_priority = ["image/png", "text/plain"]
+break_before_headings = [1, 2]
+
+[page]
+paper = "us-letter"
+margin = "2cm"
+
+[style]
+code_bg = "#f4f4f4"
+result_stroke = "1pt + gray"
+
+[tags]
+solution = "remove-cell"
+
+[lang_map]
+IR = "r"
+"##,
+        )
+        .unwrap();
+        assert_eq!(opts.theme, "report");
+        assert_eq!(
+            opts.template.map(PathBuf::from),
+            Some(Path::new("dir").join("custom.typ"))
+        );
+        assert!(opts.compile);
+        assert_eq!(opts.toc_depth, 2);
+        assert_eq!(opts.mime_priority, ["image/png", "text/plain"]);
+        assert_eq!(opts.break_before_headings, [1, 2]);
+        assert_eq!(opts.page.paper, "us-letter");
+        assert_eq!(opts.page.margin.as_deref(), Some("2cm"));
+        assert_eq!(opts.style.code_bg.as_deref(), Some("rgb(\"f4f4f4\")"));
+        assert_eq!(opts.style.result_stroke.as_deref(), Some("1pt + gray"));
+        assert_eq!(opts.tags.get("solution"), Some(&TagAction::RemoveCell));
+        assert_eq!(opts.lang_map.get("ir").map(String::as_str), Some("r"));
+    }
+
+    #[test]
+    fn invalid_keys() {
+        assert_eq!(
+            error("colour = \"red\""),
+            "dir/jupyter2typst.toml: unknown key `colour`"
+        );
+        assert_eq!(
+            error("[page]\npaper_size = \"a5\""),
+            "dir/jupyter2typst.toml: unknown key `page.paper_size`"
+        );
+        assert_eq!(
+            error("[style]\nborder = \"1pt\""),
+            "dir/jupyter2typst.toml: unknown key `style.border`"
+        );
+        assert_eq!(
+            error("toc = \"yes\""),
+            "dir/jupyter2typst.toml: `toc` must be a boolean"
+        );
+        assert!(error("[style]\ncode_bg = \"red.darken(10%)\"").contains("invalid color"));
+    }
+
+    #[test]
+    fn request_keys() {
+        let request = |key: &str| {
+            let mut options = HashMap::new();
+            options.insert(key.to_string(), JsonValue::String("x".to_string()));
+            Options::default().apply_request_json(options.into())
+        };
+        assert_eq!(
+            request("template").unwrap_err().to_string(),
+            "options: `template` can't be set in requests"
+        );
+        assert!(request("theme").is_ok());
+    }
+
+    #[test]
+    fn flags() {
+        // The configuration file's value, unless overridden on the command line.
+        assert!(!flag(false, false, false));
+        assert!(flag(true, false, false));
+        assert!(flag(false, true, false));
+        assert!(!flag(true, false, true));
+        // `--no-x` wins over `--x`.
+        assert!(!flag(true, true, true));
+    }
+}
@@ -0,0 +1,206 @@
+//! Definition lists, as in Pandoc and MyST Markdown, which the Markdown parser doesn't support:
+//!
+//! ```text
+//! Term
+//! : Definition, possibly continued
+//!   on indented lines.
+//! : Another definition of the same term.
+//! ```
+//!
+//! The source is split into runs of ordinary Markdown and definition lists, which become
+//! `#terms` lists.
+
+/// A part of a Markdown source.
+pub enum Segment<'a> {
+    Markdown(String),
+    Definitions(Vec<Definition<'a>>),
+}
+
+/// A term and the Markdown sources of its definitions.
+pub struct Definition<'a> {
+    pub term: &'a str,
+    pub definitions: Vec<String>,
+}
+
+/// Split `source` into ordinary Markdown and definition lists. Fenced code blocks are left alone.
+pub fn split_definition_lists(source: &str) -> Vec<Segment<'_>> {
+    let lines = source.lines().collect::<Vec<_>>();
+    let mut segments = vec![];
+    let mut markdown = String::new();
+    let mut fence: Option<&str> = None;
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        if let Some(open) = fence {
+            if line.trim_start().starts_with(open) {
+                fence = None;
+            }
+        } else if let Some(open) = fence_marker(line) {
+            fence = Some(open);
+        } else if is_term(&lines, i, false) {
+            let (definitions, end) = parse_list(&lines, i);
+            if !markdown.is_empty() {
+                segments.push(Segment::Markdown(std::mem::take(&mut markdown)));
+            }
+            segments.push(Segment::Definitions(definitions));
+            i = end;
+            continue;
+        }
+        markdown.push_str(line);
+        markdown.push('\n');
+        i += 1;
+    }
+    if !markdown.is_empty() || segments.is_empty() {
+        segments.push(Segment::Markdown(markdown));
+    }
+    segments
+}
+
+/// The backtick or tilde run opening a fenced code block in `line`, if it does.
+fn fence_marker(line: &str) -> Option<&str> {
+    let trimmed = line.trim_start();
+    let c = trimmed.chars().next().filter(|c| *c == '`' || *c == '~')?;
+    let len = trimmed.len() - trimmed.trim_start_matches(c).len();
+    (len >= 3).then(|| &trimmed[..len])
+}
+
+/// The text of a definition line `: text`, if `line` is one.
+fn definition_marker(line: &str) -> Option<&str> {
+    let rest = line.strip_prefix(':')?;
+    rest.starts_with([' ', '\t']).then(|| rest.trim_start())
+}
+
+/// Whether line `i` is a term: an unindented line followed by a definition, optionally after one
+/// blank line. Outside of a list, it must also start a block.
+fn is_term(lines: &[&str], i: usize, in_list: bool) -> bool {
+    let line = match lines.get(i) {
+        Some(line) => line,
+        None => return false,
+    };
+    if line.trim().is_empty()
+        || line.starts_with([' ', '\t', '#', ':'])
+        || (!in_list && i > 0 && !lines[i - 1].trim().is_empty())
+    {
+        return false;
+    }
+    let next = match lines.get(i + 1) {
+        Some(l) if l.trim().is_empty() => lines.get(i + 2),
+        next => next,
+    };
+    next.map_or(false, |l| definition_marker(l).is_some())
+}
+
+/// Parse the definition list starting at line `start`, and return it and the line after it.
+fn parse_list<'a>(lines: &[&'a str], start: usize) -> (Vec<Definition<'a>>, usize) {
+    let mut items = vec![];
+    let mut i = start;
+    while is_term(lines, i, true) {
+        let mut item = Definition {
+            term: lines[i].trim(),
+            definitions: vec![],
+        };
+        i += 1;
+        loop {
+            // A definition may be separated from the term or the previous one by a blank line.
+            let skip = usize::from(lines.get(i).map_or(false, |l| l.trim().is_empty()));
+            let text = match lines.get(i + skip).and_then(|l| definition_marker(l)) {
+                Some(text) => text,
+                None => break,
+            };
+            i += skip + 1;
+            let mut definition = format!("{}\n", text);
+            // Continuation lines are indented, possibly after blank lines.
+            while i < lines.len() {
+                let blank = lines[i..]
+                    .iter()
+                    .take_while(|l| l.trim().is_empty())
+                    .count();
+                match lines.get(i + blank) {
+                    Some(l) if l.starts_with([' ', '\t']) => {
+                        for _ in 0..blank {
+                            definition.push('\n');
+                        }
+                        definition.push_str(dedent(l));
+                        definition.push('\n');
+                        i += blank + 1;
+                    }
+                    // Lazy continuation of the paragraph, unless it starts the next term.
+                    Some(l)
+                        if blank == 0
+                            && definition_marker(l).is_none()
+                            && !is_term(lines, i, true) =>
+                    {
+                        definition.push_str(l);
+                        definition.push('\n');
+                        i += 1;
+                    }
+                    _ => break,
+                }
+            }
+            item.definitions.push(definition);
+        }
+        items.push(item);
+        // Items may be separated by a blank line.
+        if lines.get(i).map_or(false, |l| l.trim().is_empty()) && is_term(lines, i + 1, true) {
+            i += 1;
+        }
+    }
+    (items, i)
+}
+
+/// Remove up to four columns of indentation from a continuation line.
+fn dedent(line: &str) -> &str {
+    if let Some(rest) = line.strip_prefix('\t') {
+        return rest;
+    }
+    let spaces = line.len() - line.trim_start_matches(' ').len();
+    &line[spaces.min(4)..]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The segments of `source`, with definition lists written as `term => [definitions]`.
+    fn split(source: &str) -> Vec<String> {
+        split_definition_lists(source)
+            .into_iter()
+            .map(|segment| match segment {
+                Segment::Markdown(markdown) => markdown,
+                Segment::Definitions(items) => items
+                    .iter()
+                    .map(|item| format!("{} => {:?}", item.term, item.definitions))
+                    .collect::<Vec<_>>()
+                    .join("; "),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn definitions() {
+        assert_eq!(
+            split("Intro.\n\nApple\n: A fruit,\n  often red.\n: A company.\n\nPear\n: Another fruit.\n\nOutro.\n"),
+            [
+                "Intro.\n\n",
+                "Apple => [\"A fruit,\\noften red.\\n\", \"A company.\\n\"]; Pear => [\"Another fruit.\\n\"]",
+                "\nOutro.\n",
+            ]
+        );
+    }
+
+    #[test]
+    fn code_fences() {
+        let source = "```python\nTerm\n: not a definition\n```\n";
+        assert_eq!(split(source), [source]);
+        let source = "~~~~\nTerm\n: not a definition\n~~~\n~~~~\n";
+        assert_eq!(split(source), [source]);
+    }
+
+    #[test]
+    fn paragraphs() {
+        // A term must start a block.
+        let source = "Some text\nTerm\n: not a definition\n";
+        assert_eq!(split(source), [source]);
+        assert_eq!(split(""), [""]);
+    }
+}
@@ -0,0 +1,99 @@
+//! Warnings and errors collected during conversion, and the JSON report written for `--report`.
+
+use crate::J2TError;
+
+use tinyjson::JsonValue;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+
+/// Exit status for invalid options, configuration or I/O failures.
+pub const EXIT_USAGE: i32 = 1;
+/// Exit status if a notebook could not be parsed.
+pub const EXIT_PARSE: i32 = 2;
+/// Exit status if a notebook could not be converted.
+pub const EXIT_CONVERSION: i32 = 3;
+/// Exit status if all notebooks were converted, but with warnings (only with `--report`).
+pub const EXIT_WARNINGS: i32 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub file: String,
+    pub cell: Option<usize>,
+    pub output: Option<usize>,
+    pub reason: String,
+}
+
+impl Diagnostic {
+    /// A failure to convert `file` as a whole.
+    pub fn error(file: &str, reason: String) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Error,
+            file: file.to_string(),
+            cell: None,
+            output: None,
+            reason,
+        }
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        let index = |i: Option<usize>| i.map_or(JsonValue::Null, |i| JsonValue::Number(i as f64));
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        let mut obj = HashMap::new();
+        obj.insert("severity".to_string(), severity.to_string().into());
+        obj.insert("file".to_string(), self.file.clone().into());
+        obj.insert("cell".to_string(), index(self.cell));
+        obj.insert("output".to_string(), index(self.output));
+        obj.insert("reason".to_string(), self.reason.clone().into());
+        obj.into()
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.file)?;
+        if let Some(cell) = self.cell {
+            write!(f, ": cell {}", cell)?;
+        }
+        if let Some(output) = self.output {
+            write!(f, ", output {}", output)?;
+        }
+        write!(f, ": {}", self.reason)
+    }
+}
+
+/// Exit status for a run producing `diagnostics`; `errors` is the exit status of the most severe
+/// failed conversion, if any.
+pub fn exit_code(diagnostics: &[Diagnostic], errors: Option<i32>) -> i32 {
+    match errors {
+        Some(code) => code,
+        None if diagnostics.iter().any(|d| d.severity == Severity::Warning) => EXIT_WARNINGS,
+        None => 0,
+    }
+}
+
+/// Write `diagnostics` as JSON into the file `path`.
+pub fn write_report(path: &str, diagnostics: &[Diagnostic]) -> Result<(), J2TError> {
+    let mut report = HashMap::new();
+    report.insert(
+        "diagnostics".to_string(),
+        JsonValue::Array(diagnostics.iter().map(Diagnostic::to_json).collect()),
+    );
+    let json = JsonValue::from(report).format().map_err(|e| J2TError {
+        msg: Some(e.to_string()),
+        ..Default::default()
+    })?;
+    fs::write(path, json)?;
+    Ok(())
+}
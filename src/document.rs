@@ -0,0 +1,503 @@
+//! Document-level settings and Typst formatting helpers: page setup, block styling and the
+//! title block.
+
+use crate::STDIO_PATH;
+
+use tinyjson::JsonValue;
+
+use std::collections::HashMap;
+use std::fmt::{self, Write};
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Quote `s` as a Typst string literal.
+pub fn typst_string(s: &str) -> String {
+    format!("\"{}\"", typst_escape(s))
+}
+
+/// Write `s` as a Typst string literal into `out`, without building an escaped copy first.
+pub fn write_typst_string(out: &mut dyn Write, s: &str) -> fmt::Result {
+    out.write_char('"')?;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        let escaped = match c {
+            '\\' => "\\\\",
+            '"' => "\\\"",
+            '\n' => "\\n",
+            '\r' => "\\r",
+            _ => continue,
+        };
+        out.write_str(&s[start..i])?;
+        out.write_str(escaped)?;
+        start = i + c.len_utf8();
+    }
+    out.write_str(&s[start..])?;
+    out.write_char('"')
+}
+
+/// Whether markup `rest` following an embedded call like `#f(..)[..]` would continue it, as
+/// arguments or a field access, so that the call must be ended with `;`.
+pub fn needs_call_terminator(rest: &str) -> bool {
+    let field = rest.strip_prefix('.').map_or(false, |r| {
+        r.starts_with(|c: char| c.is_alphabetic() || c == '_')
+    });
+    rest.starts_with(['[', '(']) || field
+}
+
+/// Escape `s` for use inside a Typst string literal.
+pub fn typst_escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+/// Format a point in time as `YYYY-MM-DD HH:MM UTC`.
+pub fn format_timestamp(t: SystemTime) -> String {
+    let secs = t
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let date = Date::from_system_time(t).unwrap_or(Date {
+        year: 1970,
+        month: 1,
+        day: 1,
+    });
+    format!(
+        "{} {:02}:{:02} UTC",
+        date,
+        secs % 86400 / 3600,
+        secs % 3600 / 60
+    )
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Date {
+    pub year: i64,
+    pub month: u32,
+    pub day: u32,
+}
+
+impl Date {
+    /// Convert a point in time into a (UTC) calendar date.
+    pub fn from_system_time(t: SystemTime) -> Option<Date> {
+        let days = (t.duration_since(UNIX_EPOCH).ok()?.as_secs() / 86400) as i64;
+        // Howard Hinnant's `civil_from_days`.
+        let z = days + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z.rem_euclid(146097);
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+        Some(Date { year, month, day })
+    }
+
+    /// The number of days in `month` of `year`.
+    fn days_in_month(year: i64, month: u32) -> u32 {
+        match month {
+            2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+            2 => 28,
+            4 | 6 | 9 | 11 => 30,
+            _ => 31,
+        }
+    }
+
+    pub fn to_typst(self) -> String {
+        format!(
+            "datetime(year: {}, month: {}, day: {})",
+            self.year, self.month, self.day
+        )
+    }
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+impl std::str::FromStr for Date {
+    type Err = String;
+
+    /// Parse a `YYYY-MM-DD` date.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("invalid date '{}' (expected YYYY-MM-DD)", s);
+        let parts = s.trim().split('-').collect::<Vec<_>>();
+        if parts.len() != 3 {
+            return Err(err());
+        }
+        let date = Date {
+            year: parts[0].parse().map_err(|_| err())?,
+            month: parts[1].parse().map_err(|_| err())?,
+            day: parts[2].parse().map_err(|_| err())?,
+        };
+        if !(1..=12).contains(&date.month)
+            || !(1..=Date::days_in_month(date.year, date.month)).contains(&date.day)
+        {
+            return Err(err());
+        }
+        Ok(date)
+    }
+}
+
+/// Check that `s` is a Typst length such as `2.5cm` or `11pt`.
+pub fn typst_length(s: &str) -> Result<String, String> {
+    let s = s.trim();
+    let unit_start = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    let (number, unit) = s.split_at(unit_start);
+    if number.parse::<f64>().is_ok() && ["pt", "mm", "cm", "in", "em"].contains(&unit) {
+        Ok(s.to_string())
+    } else {
+        Err(format!(
+            "invalid length '{}' (expected a number with unit pt, mm, cm, in or em)",
+            s
+        ))
+    }
+}
+
+/// Arrangement of the cells on the page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    OneColumn,
+    /// Two columns, with cells tagged `fullwidth` spanning both.
+    TwoColumn,
+}
+
+impl std::str::FromStr for Layout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "onecol" => Ok(Layout::OneColumn),
+            "twocol" => Ok(Layout::TwoColumn),
+            _ => Err(format!(
+                "unknown layout '{}' (expected 'onecol' or 'twocol')",
+                s
+            )),
+        }
+    }
+}
+
+/// Page and typography settings emitted as `set` rules in the preamble.
+#[derive(Debug)]
+pub struct PageSetup {
+    pub paper: String,
+    pub margin: Option<String>,
+    pub font: Option<String>,
+    pub font_size: Option<String>,
+    pub code_font_size: Option<String>,
+}
+
+impl Default for PageSetup {
+    fn default() -> PageSetup {
+        PageSetup {
+            paper: "a4".to_string(),
+            margin: None,
+            font: None,
+            font_size: None,
+            code_font_size: None,
+        }
+    }
+}
+
+impl PageSetup {
+    pub fn to_typst(&self) -> String {
+        let mut out = String::new();
+        write!(out, "#set page(paper: {}", typst_string(&self.paper)).unwrap();
+        if let Some(ref margin) = self.margin {
+            write!(out, ", margin: {}", margin).unwrap();
+        }
+        out.push_str(")\n");
+
+        let mut text_args = vec![];
+        if let Some(ref font) = self.font {
+            text_args.push(format!("font: {}", typst_string(font)));
+        }
+        if let Some(ref size) = self.font_size {
+            text_args.push(format!("size: {}", size));
+        }
+        if !text_args.is_empty() {
+            writeln!(out, "#set text({})", text_args.join(", ")).unwrap();
+        }
+        if let Some(ref size) = self.code_font_size {
+            writeln!(out, "#show raw: set text(size: {})", size).unwrap();
+        }
+        out.push('\n');
+        out
+    }
+}
+
+/// Colors predefined by Typst.
+const COLOR_NAMES: &[&str] = &[
+    "black", "gray", "silver", "white", "navy", "blue", "aqua", "teal", "eastern", "purple",
+    "fuchsia", "maroon", "red", "orange", "yellow", "olive", "green", "lime",
+];
+
+/// Convert a color into a Typst expression. Only hex colors like `#e0e0e0`, predefined colors
+/// like `gray` and grays like `luma(230)` are accepted, as the value ends up in the document
+/// source.
+pub fn typst_color(s: &str) -> Result<String, String> {
+    let s = s.trim();
+    if let Some(hex) = s.strip_prefix('#') {
+        if [3, 4, 6, 8].contains(&hex.len()) && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Ok(format!("rgb({})", typst_string(hex)));
+        }
+    } else if COLOR_NAMES.contains(&s) {
+        return Ok(s.to_string());
+    } else if let Some(luma) = s.strip_prefix("luma(").and_then(|l| l.strip_suffix(')')) {
+        if let Ok(luma) = luma.trim().parse::<u8>() {
+            return Ok(format!("luma({})", luma));
+        }
+    }
+    Err(format!(
+        "invalid color '{}' (expected e.g. #e0e0e0, gray or luma(230))",
+        s
+    ))
+}
+
+/// Convert a stroke into a Typst expression: `none`, a length, a color, or a length and a color
+/// like `1pt + gray`.
+pub fn typst_stroke(s: &str) -> Result<String, String> {
+    let s = s.trim();
+    if s == "none" {
+        return Ok(s.to_string());
+    }
+    let invalid = |_| format!("invalid stroke '{}' (expected e.g. 1pt + gray or none)", s);
+    match s.split_once('+') {
+        Some((length, color)) => Ok(format!(
+            "{} + {}",
+            typst_length(length).map_err(invalid)?,
+            typst_color(color).map_err(invalid)?
+        )),
+        None => typst_length(s).or_else(|_| typst_color(s)).map_err(invalid),
+    }
+}
+
+/// Overrides for the theme's code and result block styling.
+#[derive(Debug, Default)]
+pub struct BlockStyle {
+    pub code_bg: Option<String>,
+    pub result_bg: Option<String>,
+    pub result_stroke: Option<String>,
+    pub block_radius: Option<String>,
+}
+
+impl BlockStyle {
+    /// Emit the overridden theme variables; must follow the theme in the preamble.
+    pub fn to_typst(&self) -> String {
+        let mut out = String::new();
+        let overrides = [
+            ("bgcolor_code", &self.code_bg),
+            ("bgcolor_result", &self.result_bg),
+            ("stroke_result", &self.result_stroke),
+            ("block_radius", &self.block_radius),
+        ];
+        for (name, value) in overrides.iter() {
+            if let Some(value) = value {
+                writeln!(out, "#let {} = {}", name, value).unwrap();
+            }
+        }
+        out
+    }
+}
+
+/// Document-level information shown on the title block.
+pub struct DocumentInfo {
+    pub title: String,
+    pub authors: Vec<String>,
+    pub date: Option<Date>,
+}
+
+impl DocumentInfo {
+    /// Collect title, authors and date from the notebook metadata. `metadata.authors` is a list
+    /// of `{"name": ...}` objects in nbformat; plain strings are accepted as well. The date is
+    /// taken from `metadata.date` if present, and is the file's modification date otherwise.
+    pub fn from_metadata(metadata: &HashMap<String, JsonValue>, infile: &str) -> DocumentInfo {
+        let title = metadata
+            .get("title")
+            .and_then(|t| t.get::<String>())
+            .cloned()
+            .unwrap_or_else(|| {
+                if infile == STDIO_PATH {
+                    return String::new();
+                }
+                Path::new(infile)
+                    .file_stem()
+                    .map(|s| s.to_string_lossy().into_owned())
+                    .unwrap_or_default()
+            });
+        let authors = metadata
+            .get("authors")
+            .and_then(|a| a.get::<Vec<JsonValue>>())
+            .map(|authors| {
+                authors
+                    .iter()
+                    .filter_map(|a| match a {
+                        JsonValue::String(name) => Some(name.clone()),
+                        JsonValue::Object(o) => {
+                            o.get("name").and_then(|n| n.get::<String>()).cloned()
+                        }
+                        _ => None,
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        // A date in the metadata that doesn't parse, like Quarto's `today`, is ignored.
+        let date = metadata
+            .get("date")
+            .and_then(|d| d.get::<String>())
+            .and_then(|d| d.parse().ok())
+            .or_else(|| {
+                fs::metadata(infile)
+                    .and_then(|m| m.modified())
+                    .ok()
+                    .and_then(Date::from_system_time)
+            });
+        DocumentInfo {
+            title,
+            authors,
+            date,
+        }
+    }
+
+    /// Emit the `#set document(...)` call and the title block.
+    pub fn to_typst(&self) -> String {
+        let authors = self
+            .authors
+            .iter()
+            .map(|a| typst_string(a))
+            .collect::<Vec<_>>();
+        let mut out = String::new();
+        writeln!(
+            out,
+            "#set document(title: {}, author: ({}{}), date: {})",
+            typst_string(&self.title),
+            authors.join(", "),
+            if authors.len() == 1 { "," } else { "" },
+            self.date.map(Date::to_typst).unwrap_or("none".to_string())
+        )
+        .unwrap();
+        out.push_str("#align(center)[\n");
+        if !self.title.is_empty() {
+            writeln!(
+                out,
+                "  #text(size: 20pt, weight: \"bold\", {})",
+                typst_string(&self.title)
+            )
+            .unwrap();
+        }
+        if !authors.is_empty() {
+            writeln!(
+                out,
+                "\n  #text(size: 12pt, {})",
+                typst_string(&self.authors.join(", "))
+            )
+            .unwrap();
+        }
+        if let Some(date) = self.date {
+            writeln!(out, "\n  #text(size: 11pt, {}.display())", date.to_typst()).unwrap();
+        }
+        out.push_str("]\n\n");
+        out
+    }
+}
+
+/// Per-document values overriding the notebook metadata, e.g. from the command line.
+#[derive(Debug, Default)]
+pub struct DocumentOverrides {
+    pub title: Option<String>,
+    pub authors: Option<Vec<String>>,
+    pub date: Option<Date>,
+}
+
+impl DocumentOverrides {
+    pub fn apply(&self, info: &mut DocumentInfo) {
+        if let Some(ref title) = self.title {
+            info.title = title.clone();
+        }
+        if let Some(ref authors) = self.authors {
+            info.authors = authors.clone();
+        }
+        if self.date.is_some() {
+            info.date = self.date;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dates() {
+        assert_eq!(
+            "2024-02-29".parse(),
+            Ok(Date {
+                year: 2024,
+                month: 2,
+                day: 29
+            })
+        );
+        assert_eq!(
+            "2000-02-29"
+                .parse::<Date>()
+                .map(|d| d.to_string())
+                .as_deref(),
+            Ok("2000-02-29")
+        );
+        assert!("2023-12-31".parse::<Date>().is_ok());
+        for invalid in [
+            "2023-02-29",
+            "1900-02-29",
+            "2023-02-31",
+            "2023-04-31",
+            "2023-13-01",
+            "2023-00-10",
+            "2023-1-0",
+            "2023-01",
+            "today",
+        ] {
+            assert_eq!(
+                invalid.parse::<Date>(),
+                Err(format!("invalid date '{}' (expected YYYY-MM-DD)", invalid))
+            );
+        }
+    }
+
+    #[test]
+    fn colors() {
+        assert_eq!(typst_color("#e0e0e0").as_deref(), Ok("rgb(\"e0e0e0\")"));
+        assert_eq!(typst_color(" gray ").as_deref(), Ok("gray"));
+        assert_eq!(typst_color("luma( 230 )").as_deref(), Ok("luma(230)"));
+        for invalid in [
+            "#e0e0e",
+            "#zzz",
+            "luma(256)",
+            "grey",
+            "red.lighten(50%)",
+            "read(\"x\")",
+        ] {
+            assert!(typst_color(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn strokes() {
+        assert_eq!(typst_stroke("none").as_deref(), Ok("none"));
+        assert_eq!(typst_stroke("1pt").as_deref(), Ok("1pt"));
+        assert_eq!(typst_stroke("#ccc").as_deref(), Ok("rgb(\"ccc\")"));
+        assert_eq!(
+            typst_stroke("0.5pt + luma(200)").as_deref(),
+            Ok("0.5pt + luma(200)")
+        );
+        for invalid in ["1pt + 2pt", "1px", "1pt + gray\n#read(\"/etc/passwd\")", ""] {
+            assert!(typst_stroke(invalid).is_err(), "{}", invalid);
+        }
+    }
+}
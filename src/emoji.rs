@@ -0,0 +1,364 @@
+//! Emoji in Markdown text: `:rocket:`-style shortcodes become Unicode characters, and emoji can
+//! be set in a dedicated font for text fonts lacking them.
+
+use crate::document::{needs_call_terminator, typst_string};
+
+use std::borrow::Cow;
+use std::fmt::Write;
+
+/// Replace known shortcodes in `text` by their emoji. If `font` is given, emoji (including those
+/// already written as Unicode) are wrapped in a `#text` call using it.
+pub fn replace_emoji<'a>(text: &'a str, font: Option<&str>) -> Cow<'a, str> {
+    if !text.contains(':') && (font.is_none() || !text.chars().any(is_emoji)) {
+        return Cow::Borrowed(text);
+    }
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        let (emoji, len) = if c == ':' {
+            match shortcode(rest) {
+                Some((emoji, len)) => (emoji, len),
+                None => {
+                    out.push(c);
+                    rest = &rest[1..];
+                    continue;
+                }
+            }
+        } else if font.is_some() && is_emoji(c) {
+            let len = rest
+                .char_indices()
+                .find(|&(_, c)| !is_emoji(c) && !is_emoji_modifier(c))
+                .map_or(rest.len(), |(i, _)| i);
+            (&rest[..len], len)
+        } else {
+            out.push(c);
+            rest = &rest[c.len_utf8()..];
+            continue;
+        };
+        rest = &rest[len..];
+        match font {
+            Some(font) => {
+                write!(out, "#text(font: {})[{}]", typst_string(font), emoji).unwrap();
+                if needs_call_terminator(rest) {
+                    out.push(';');
+                }
+            }
+            None => out.push_str(emoji),
+        }
+    }
+    Cow::Owned(out)
+}
+
+/// The emoji for the shortcode at the start of `s`, and the shortcode's length.
+fn shortcode(s: &str) -> Option<(&'static str, usize)> {
+    let end = s[1..].find(':')? + 1;
+    let name = &s[1..end];
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_+-".contains(c))
+    {
+        return None;
+    }
+    SHORTCODES
+        .iter()
+        .find(|(code, _)| *code == name)
+        .map(|(_, emoji)| (*emoji, end + 1))
+}
+
+fn is_emoji(c: char) -> bool {
+    matches!(c as u32,
+        0x1F000..=0x1FAFF | 0x2600..=0x27BF | 0x2B05..=0x2B55 | 0x231A..=0x23FF)
+}
+
+/// Characters continuing an emoji: joiners, variation selectors, skin tones and keycaps.
+fn is_emoji_modifier(c: char) -> bool {
+    matches!(
+        c as u32,
+        0x200D | 0xFE0E | 0xFE0F | 0x20E3 | 0xE0020..=0xE007F
+    )
+}
+
+/// Common shortcodes, as used on GitHub and in JupyterLab extensions.
+const SHORTCODES: &[(&str, &str)] = &[
+    ("+1", "👍"),
+    ("-1", "👎"),
+    ("thumbsup", "👍"),
+    ("thumbsdown", "👎"),
+    ("smile", "😄"),
+    ("smiley", "😃"),
+    ("grinning", "😀"),
+    ("grin", "😁"),
+    ("laughing", "😆"),
+    ("joy", "😂"),
+    ("rofl", "🤣"),
+    ("blush", "😊"),
+    ("wink", "😉"),
+    ("slightly_smiling_face", "🙂"),
+    ("upside_down_face", "🙃"),
+    ("heart_eyes", "😍"),
+    ("sunglasses", "😎"),
+    ("thinking", "🤔"),
+    ("neutral_face", "😐"),
+    ("expressionless", "😑"),
+    ("confused", "😕"),
+    ("worried", "😟"),
+    ("frowning", "😦"),
+    ("cry", "😢"),
+    ("sob", "😭"),
+    ("scream", "😱"),
+    ("angry", "😠"),
+    ("rage", "😡"),
+    ("sweat_smile", "😅"),
+    ("sweat", "😓"),
+    ("sleeping", "😴"),
+    ("open_mouth", "😮"),
+    ("astonished", "😲"),
+    ("exploding_head", "🤯"),
+    ("partying_face", "🥳"),
+    ("nerd_face", "🤓"),
+    ("face_with_monocle", "🧐"),
+    ("raised_eyebrow", "🤨"),
+    ("shrug", "🤷"),
+    ("facepalm", "🤦"),
+    ("wave", "👋"),
+    ("clap", "👏"),
+    ("raised_hands", "🙌"),
+    ("pray", "🙏"),
+    ("ok_hand", "👌"),
+    ("point_right", "👉"),
+    ("point_left", "👈"),
+    ("point_up", "☝️"),
+    ("point_down", "👇"),
+    ("muscle", "💪"),
+    ("eyes", "👀"),
+    ("brain", "🧠"),
+    ("heart", "❤️"),
+    ("broken_heart", "💔"),
+    ("sparkling_heart", "💖"),
+    ("star", "⭐"),
+    ("star2", "🌟"),
+    ("sparkles", "✨"),
+    ("zap", "⚡"),
+    ("fire", "🔥"),
+    ("boom", "💥"),
+    ("collision", "💥"),
+    ("100", "💯"),
+    ("tada", "🎉"),
+    ("confetti_ball", "🎊"),
+    ("trophy", "🏆"),
+    ("medal_sports", "🏅"),
+    ("1st_place_medal", "🥇"),
+    ("dart", "🎯"),
+    ("rocket", "🚀"),
+    ("airplane", "✈️"),
+    ("car", "🚗"),
+    ("construction", "🚧"),
+    ("rotating_light", "🚨"),
+    ("warning", "⚠️"),
+    ("no_entry", "⛔"),
+    ("no_entry_sign", "🚫"),
+    ("x", "❌"),
+    ("heavy_check_mark", "✔️"),
+    ("white_check_mark", "✅"),
+    ("ballot_box_with_check", "☑️"),
+    ("heavy_multiplication_x", "✖️"),
+    ("heavy_plus_sign", "➕"),
+    ("heavy_minus_sign", "➖"),
+    ("question", "❓"),
+    ("grey_question", "❔"),
+    ("exclamation", "❗"),
+    ("grey_exclamation", "❕"),
+    ("bangbang", "‼️"),
+    ("interrobang", "⁉️"),
+    ("information_source", "ℹ️"),
+    ("arrow_right", "➡️"),
+    ("arrow_left", "⬅️"),
+    ("arrow_up", "⬆️"),
+    ("arrow_down", "⬇️"),
+    ("arrows_counterclockwise", "🔄"),
+    ("repeat", "🔁"),
+    ("link", "🔗"),
+    ("lock", "🔒"),
+    ("unlock", "🔓"),
+    ("key", "🔑"),
+    ("bell", "🔔"),
+    ("mag", "🔍"),
+    ("mag_right", "🔎"),
+    ("bulb", "💡"),
+    ("memo", "📝"),
+    ("pencil", "📝"),
+    ("pencil2", "✏️"),
+    ("book", "📖"),
+    ("books", "📚"),
+    ("notebook", "📓"),
+    ("bookmark", "🔖"),
+    ("clipboard", "📋"),
+    ("pushpin", "📌"),
+    ("paperclip", "📎"),
+    ("scissors", "✂️"),
+    ("file_folder", "📁"),
+    ("open_file_folder", "📂"),
+    ("page_facing_up", "📄"),
+    ("calendar", "📆"),
+    ("date", "📅"),
+    ("chart_with_upwards_trend", "📈"),
+    ("chart_with_downwards_trend", "📉"),
+    ("bar_chart", "📊"),
+    ("email", "📧"),
+    ("envelope", "✉️"),
+    ("inbox_tray", "📥"),
+    ("outbox_tray", "📤"),
+    ("package", "📦"),
+    ("computer", "💻"),
+    ("desktop_computer", "🖥️"),
+    ("keyboard", "⌨️"),
+    ("floppy_disk", "💾"),
+    ("cd", "💿"),
+    ("iphone", "📱"),
+    ("phone", "☎️"),
+    ("battery", "🔋"),
+    ("electric_plug", "🔌"),
+    ("gear", "⚙️"),
+    ("wrench", "🔧"),
+    ("hammer", "🔨"),
+    ("hammer_and_wrench", "🛠️"),
+    ("nut_and_bolt", "🔩"),
+    ("microscope", "🔬"),
+    ("telescope", "🔭"),
+    ("test_tube", "🧪"),
+    ("dna", "🧬"),
+    ("abacus", "🧮"),
+    ("triangular_ruler", "📐"),
+    ("straight_ruler", "📏"),
+    ("hourglass", "⌛"),
+    ("hourglass_flowing_sand", "⏳"),
+    ("stopwatch", "⏱️"),
+    ("alarm_clock", "⏰"),
+    ("watch", "⌚"),
+    ("moneybag", "💰"),
+    ("dollar", "💵"),
+    ("euro", "💶"),
+    ("gift", "🎁"),
+    ("balloon", "🎈"),
+    ("art", "🎨"),
+    ("musical_note", "🎵"),
+    ("notes", "🎶"),
+    ("game_die", "🎲"),
+    ("jigsaw", "🧩"),
+    ("robot", "🤖"),
+    ("alien", "👽"),
+    ("ghost", "👻"),
+    ("skull", "💀"),
+    ("poop", "💩"),
+    ("hankey", "💩"),
+    ("bug", "🐛"),
+    ("snake", "🐍"),
+    ("panda_face", "🐼"),
+    ("crab", "🦀"),
+    ("penguin", "🐧"),
+    ("whale", "🐳"),
+    ("octopus", "🐙"),
+    ("bee", "🐝"),
+    ("honeybee", "🐝"),
+    ("cat", "🐱"),
+    ("dog", "🐶"),
+    ("unicorn", "🦄"),
+    ("turtle", "🐢"),
+    ("snail", "🐌"),
+    ("seedling", "🌱"),
+    ("herb", "🌿"),
+    ("evergreen_tree", "🌲"),
+    ("deciduous_tree", "🌳"),
+    ("cactus", "🌵"),
+    ("four_leaf_clover", "🍀"),
+    ("fallen_leaf", "🍂"),
+    ("rose", "🌹"),
+    ("sunflower", "🌻"),
+    ("earth_africa", "🌍"),
+    ("earth_americas", "🌎"),
+    ("earth_asia", "🌏"),
+    ("globe_with_meridians", "🌐"),
+    ("sunny", "☀️"),
+    ("cloud", "☁️"),
+    ("umbrella", "☔"),
+    ("snowflake", "❄️"),
+    ("rainbow", "🌈"),
+    ("ocean", "🌊"),
+    ("droplet", "💧"),
+    ("crescent_moon", "🌙"),
+    ("coffee", "☕"),
+    ("tea", "🍵"),
+    ("beer", "🍺"),
+    ("pizza", "🍕"),
+    ("cake", "🍰"),
+    ("apple", "🍎"),
+    ("banana", "🍌"),
+    ("cookie", "🍪"),
+    ("house", "🏠"),
+    ("office", "🏢"),
+    ("school", "🏫"),
+    ("hospital", "🏥"),
+    ("checkered_flag", "🏁"),
+    ("triangular_flag_on_post", "🚩"),
+    ("white_flag", "🏳️"),
+    ("red_circle", "🔴"),
+    ("orange_circle", "🟠"),
+    ("yellow_circle", "🟡"),
+    ("green_circle", "🟢"),
+    ("large_blue_circle", "🔵"),
+    ("blue_circle", "🔵"),
+    ("purple_circle", "🟣"),
+    ("black_circle", "⚫"),
+    ("white_circle", "⚪"),
+    ("red_square", "🟥"),
+    ("green_square", "🟩"),
+    ("large_blue_diamond", "🔷"),
+    ("large_orange_diamond", "🔶"),
+    ("small_blue_diamond", "🔹"),
+    ("small_orange_diamond", "🔸"),
+    ("new", "🆕"),
+    ("free", "🆓"),
+    ("up", "🆙"),
+    ("cool", "🆒"),
+    ("ok", "🆗"),
+    ("sos", "🆘"),
+    ("soon", "🔜"),
+    ("top", "🔝"),
+    ("recycle", "♻️"),
+    ("copyright", "©️"),
+    ("registered", "®️"),
+    ("tm", "™️"),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shortcodes() {
+        assert_eq!(replace_emoji("Launch :rocket: :+1:", None), "Launch 🚀 👍");
+        assert_eq!(replace_emoji("at 10:30:rocket:", None), "at 10:30🚀");
+        assert_eq!(
+            replace_emoji("a :nosuchemoji: b", None),
+            "a :nosuchemoji: b"
+        );
+    }
+
+    #[test]
+    fn untouched() {
+        assert_eq!(replace_emoji("at 10:30: done", None), "at 10:30: done");
+        assert!(matches!(replace_emoji("no colons", None), Cow::Borrowed(_)));
+        assert_eq!(replace_emoji("key: value", None), "key: value");
+    }
+
+    #[test]
+    fn font() {
+        let font = Some("Noto Color Emoji");
+        assert_eq!(
+            replace_emoji("🎉 :tada:(x)", font),
+            "#text(font: \"Noto Color Emoji\")[🎉] #text(font: \"Noto Color Emoji\")[🎉];(x)"
+        );
+        assert_eq!(replace_emoji("plain", font), "plain");
+    }
+}
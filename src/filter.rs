@@ -0,0 +1,80 @@
+//! External filter commands, run through the shell. A cell filter receives the JSON of each cell
+//! on stdin and writes the cell to convert instead on stdout; printing nothing (or `null`) drops
+//! the cell. A post filter receives the generated Typst source and writes the final source.
+
+use crate::notebook::{json_get, json_object};
+use crate::J2TError;
+
+use tinyjson::JsonValue;
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::thread;
+
+/// Pass each cell of the notebook `nb` through the command `cmd`.
+pub fn filter_cells(cmd: &str, nb: JsonValue) -> Result<JsonValue, J2TError> {
+    let mut nb = json_object(nb, "notebook")?;
+    let cells: Vec<JsonValue> = json_get(&mut nb, "", "cells")?;
+    let mut filtered = Vec::with_capacity(cells.len());
+    for (i, cell) in cells.into_iter().enumerate() {
+        let cell_error = |e: J2TError| J2TError { cell: Some(i), ..e };
+        let json = cell.stringify().map_err(|e| J2TError {
+            msg: Some(e.to_string()),
+            cell: Some(i),
+            ..Default::default()
+        })?;
+        let output = run(cmd, json.into_bytes()).map_err(cell_error)?;
+        let output = String::from_utf8(output)
+            .map_err(|_| cell_error(filter_error(cmd, "printed invalid UTF-8".to_string())))?;
+        if output.trim().is_empty() {
+            continue;
+        }
+        match output.parse().map_err(|e: tinyjson::JsonParseError| {
+            cell_error(filter_error(cmd, format!("printed invalid JSON: {}", e)))
+        })? {
+            JsonValue::Null => {}
+            cell => filtered.push(cell),
+        }
+    }
+    nb.insert("cells".to_string(), JsonValue::Array(filtered));
+    Ok(nb.into())
+}
+
+/// Run the shell command `cmd` with `input` on stdin, and return what it prints on stdout. Its
+/// stderr is passed through.
+pub fn run(cmd: &str, input: Vec<u8>) -> Result<Vec<u8>, J2TError> {
+    debug!("running filter `{}`", cmd);
+    let (shell, flag) = if cfg!(windows) {
+        ("cmd", "/C")
+    } else {
+        ("sh", "-c")
+    };
+    let mut child = Command::new(shell)
+        .args([flag, cmd])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| filter_error(cmd, e.to_string()))?;
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    // Write from another thread, so that a filter printing a lot before reading all of its input
+    // can't block both sides.
+    let output = thread::scope(|s| {
+        s.spawn(move || {
+            // A filter may exit without reading all of its input; its exit status tells.
+            let _ = stdin.write_all(&input);
+        });
+        child.wait_with_output()
+    })
+    .map_err(|e| filter_error(cmd, e.to_string()))?;
+    if !output.status.success() {
+        return Err(filter_error(cmd, format!("failed with {}", output.status)));
+    }
+    Ok(output.stdout)
+}
+
+fn filter_error(cmd: &str, msg: String) -> J2TError {
+    J2TError {
+        msg: Some(format!("filter `{}`: {}", cmd, msg)),
+        ..Default::default()
+    }
+}
@@ -0,0 +1,279 @@
+//! Reading jupytext "percent" scripts, in which cells are delimited by `# %%` comments, as
+//! notebooks.
+//!
+//! ```text
+//! # ---
+//! # jupyter:
+//! #   kernelspec:
+//! #     language: python
+//! # ---
+//!
+//! # %% [markdown]
+//! # # Analysis
+//!
+//! # %% tags=["remove-input"]
+//! import pandas as pd
+//! ```
+//!
+//! The commented YAML header holds the notebook metadata under `jupyter`. Scripts have no
+//! outputs, so all code cells come out unexecuted.
+
+use crate::notebook::kernel_language;
+use crate::yaml;
+use crate::J2TError;
+
+use tinyjson::JsonValue;
+
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Extensions of scripts read as jupytext percent scripts, with the language of their code.
+pub const SCRIPT_LANGUAGES: &[(&str, &str)] = &[("py", "python"), ("R", "r"), ("r", "r")];
+
+/// The language of the script `path`, if it is one.
+pub fn script_language(path: &Path) -> Option<&'static str> {
+    let extension = path.extension()?.to_str()?;
+    SCRIPT_LANGUAGES
+        .iter()
+        .find(|(e, _)| *e == extension)
+        .map(|(_, language)| *language)
+}
+
+/// Convert the percent script `source` into nbformat 4 JSON. `language` is the language of the
+/// code, used if the header doesn't name a kernel.
+pub fn parse(source: &str, language: &str) -> Result<JsonValue, J2TError> {
+    let mut lines = source.lines().peekable();
+
+    let mut metadata = HashMap::new();
+    if lines.peek().map(|l| l.trim_end()) == Some("# ---") {
+        lines.next();
+        let mut header = String::new();
+        for line in lines.by_ref() {
+            if line.trim_end() == "# ---" {
+                break;
+            }
+            header.push_str(uncomment(line));
+            header.push('\n');
+        }
+        let header = yaml::parse(&header).map_err(|e| J2TError {
+            msg: Some(format!("jupytext header: {}", e)),
+            ..Default::default()
+        })?;
+        if let Some(JsonValue::Object(mut jupyter)) = header
+            .get::<HashMap<String, JsonValue>>()
+            .and_then(|h| h.get("jupyter"))
+            .cloned()
+        {
+            jupyter.remove("jupytext");
+            metadata = jupyter;
+        }
+    }
+    if kernel_language(&metadata).is_none() {
+        let mut language_info = HashMap::new();
+        language_info.insert("name".to_string(), language.to_string().into());
+        metadata.insert("language_info".to_string(), language_info.into());
+    }
+
+    let mut cells = vec![];
+    // Code before the first cell marker forms a cell of its own.
+    let mut cell = PendingCell::new("code", HashMap::new());
+    for line in lines {
+        match parse_marker(line) {
+            Some((cell_type, cell_metadata)) => {
+                cells.extend(cell.finish());
+                cell = PendingCell::new(cell_type, cell_metadata);
+            }
+            None => cell.lines.push(line),
+        }
+    }
+    cells.extend(cell.finish());
+
+    let mut nb = HashMap::new();
+    nb.insert("nbformat".to_string(), JsonValue::Number(4.0));
+    nb.insert("nbformat_minor".to_string(), JsonValue::Number(5.0));
+    nb.insert("metadata".to_string(), metadata.into());
+    nb.insert("cells".to_string(), JsonValue::Array(cells));
+    Ok(nb.into())
+}
+
+/// Strip the comment marker from a line of a Markdown cell or the header.
+fn uncomment(line: &str) -> &str {
+    line.strip_prefix("# ")
+        .or_else(|| line.strip_prefix('#'))
+        .unwrap_or(line)
+}
+
+/// Parse a cell marker like `# %% [markdown] tags=["intro"]` into the cell type and metadata.
+/// Anything after the marker that isn't metadata is the cell title, which is dropped.
+fn parse_marker(line: &str) -> Option<(&'static str, HashMap<String, JsonValue>)> {
+    let rest = line.strip_prefix("# %%")?;
+    if !(rest.is_empty() || rest.starts_with(' ')) {
+        return None;
+    }
+    let mut rest = rest.trim();
+    let mut cell_type = "code";
+    if let Some(bracketed) = rest.strip_prefix('[') {
+        if let Some((kind, after)) = bracketed.split_once(']') {
+            cell_type = match kind {
+                "markdown" | "md" => "markdown",
+                "raw" => "raw",
+                _ => "code",
+            };
+            rest = after.trim();
+        }
+    }
+    Some((cell_type, parse_cell_metadata(rest)))
+}
+
+/// Parse `key=<json>` pairs separated by spaces. Returns no metadata if `s` is a title instead.
+fn parse_cell_metadata(s: &str) -> HashMap<String, JsonValue> {
+    let mut metadata = HashMap::new();
+    let mut rest = s;
+    while !rest.is_empty() {
+        let (key, value) = match rest.split_once('=') {
+            Some((key, value)) if is_identifier(key) => (key, value),
+            _ => return HashMap::new(),
+        };
+        // The value extends up to the next ` key=`, or the end of the line.
+        let end = value
+            .match_indices(' ')
+            .map(|(i, _)| i)
+            .find(|&i| {
+                value[i + 1..]
+                    .split_once('=')
+                    .map_or(false, |(k, _)| is_identifier(k))
+                    && value[..i].parse::<JsonValue>().is_ok()
+            })
+            .unwrap_or(value.len());
+        match value[..end].parse::<JsonValue>() {
+            Ok(v) => metadata.insert(key.to_string(), v),
+            Err(_) => return HashMap::new(),
+        };
+        rest = value[end..].trim_start();
+    }
+    metadata
+}
+
+fn is_identifier(s: &str) -> bool {
+    !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Lines collected for the cell being read.
+struct PendingCell<'a> {
+    cell_type: &'static str,
+    metadata: HashMap<String, JsonValue>,
+    lines: Vec<&'a str>,
+}
+
+impl<'a> PendingCell<'a> {
+    fn new(cell_type: &'static str, metadata: HashMap<String, JsonValue>) -> PendingCell<'a> {
+        PendingCell {
+            cell_type,
+            metadata,
+            lines: vec![],
+        }
+    }
+
+    /// The nbformat cell, or `None` for an empty code cell.
+    fn finish(self) -> Option<JsonValue> {
+        let mut lines = self.lines;
+        // Cells are separated by blank lines, which don't belong to the cell.
+        while lines.last().map_or(false, |l| l.trim().is_empty()) {
+            lines.pop();
+        }
+        let start = lines
+            .iter()
+            .position(|l| !l.trim().is_empty())
+            .unwrap_or(lines.len());
+        let lines = &lines[start..];
+        if lines.is_empty() && self.cell_type == "code" {
+            return None;
+        }
+        let source = if self.cell_type == "code" {
+            lines.join("\n")
+        } else {
+            lines
+                .iter()
+                .map(|l| uncomment(l))
+                .collect::<Vec<_>>()
+                .join("\n")
+        };
+
+        let mut cell = HashMap::new();
+        cell.insert("cell_type".to_string(), self.cell_type.to_string().into());
+        cell.insert("source".to_string(), source.into());
+        cell.insert("metadata".to_string(), self.metadata.into());
+        if self.cell_type == "code" {
+            cell.insert("execution_count".to_string(), JsonValue::Null);
+            cell.insert("outputs".to_string(), JsonValue::Array(vec![]));
+        }
+        Some(cell.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percent_script() {
+        let source = "# ---
+# jupyter:
+#   jupytext:
+#     formats: ipynb,py:percent
+#   kernelspec:
+#     language: python
+#     name: python3
+# ---
+
+import os
+
+# %% [markdown]
+# # Analysis
+#
+# Some text.
+
+# %% Load data
+data = os.listdir()
+
+# %%
+";
+        let nb = parse(source, "r").unwrap();
+        let nb: &HashMap<String, JsonValue> = nb.get().unwrap();
+        let metadata: &HashMap<String, JsonValue> = nb["metadata"].get().unwrap();
+        assert!(!metadata.contains_key("jupytext"));
+        assert!(!metadata.contains_key("language_info"));
+        assert_eq!(kernel_language(metadata).as_deref(), Some("python"));
+
+        let cells: &Vec<JsonValue> = nb["cells"].get().unwrap();
+        let cells = cells
+            .iter()
+            .map(|cell| {
+                let cell: &HashMap<String, JsonValue> = cell.get().unwrap();
+                let cell_type: &String = cell["cell_type"].get().unwrap();
+                let source: &String = cell["source"].get().unwrap();
+                (cell_type.as_str(), source.as_str())
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            cells,
+            [
+                ("code", "import os"),
+                ("markdown", "# Analysis\n\nSome text."),
+                ("code", "data = os.listdir()"),
+            ]
+        );
+    }
+
+    #[test]
+    fn language() {
+        assert_eq!(script_language(Path::new("a.R")), Some("r"));
+        assert_eq!(script_language(Path::new("a.ipynb")), None);
+        let nb = parse("x <- 1\n", "r").unwrap();
+        let nb: &HashMap<String, JsonValue> = nb.get().unwrap();
+        let metadata: &HashMap<String, JsonValue> = nb["metadata"].get().unwrap();
+        assert_eq!(kernel_language(metadata).as_deref(), Some("r"));
+    }
+}
@@ -0,0 +1,506 @@
+//! LaTeX math, as written in Markdown cells between `$`, translated into Typst math.
+//!
+//! The commonly used part of LaTeX is covered: symbols, fractions, roots, fonts, accents, text,
+//! attachments, delimiters and matrix-like environments. Unknown commands are kept as strings
+//! and reported. User-defined macros (see `math`) become calls of their Typst functions.
+
+use crate::document::typst_string;
+use crate::math::{group_len, parse_definition, tokenize, Macros, Token};
+
+use std::fmt::Write;
+
+/// Translate the LaTeX math `latex` into Typst math. Also returns the unknown commands.
+pub fn latex_to_typst(latex: &str, macros: &Macros) -> (String, Vec<String>) {
+    translate_tokens(&tokenize(latex), macros)
+}
+
+pub fn translate_tokens(tokens: &[Token], macros: &Macros) -> (String, Vec<String>) {
+    let mut t = Translator {
+        tokens,
+        pos: 0,
+        macros,
+        unknown: vec![],
+        nested: 0,
+    };
+    let mut out = String::new();
+    t.translate_until(&mut out, |_| false);
+    (out, t.unknown)
+}
+
+struct Translator<'t, 'a, 'm> {
+    tokens: &'t [Token<'a>],
+    pos: usize,
+    macros: &'m Macros,
+    unknown: Vec<String>,
+    /// How many arguments or matrix cells are being translated. Commas and semicolons in them
+    /// are escaped, so that they don't separate the arguments or cells of Typst functions.
+    nested: usize,
+}
+
+impl<'t, 'a, 'm> Translator<'t, 'a, 'm> {
+    fn peek(&self) -> Option<Token<'a>> {
+        self.tokens.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<Token<'a>> {
+        let token = self.peek();
+        self.pos += 1;
+        token
+    }
+
+    /// Translate tokens up to the end of the input, an unmatched `}`, or a token for which `stop`
+    /// is true, which is not consumed.
+    fn translate_until(&mut self, out: &mut String, stop: fn(&Token) -> bool) {
+        while let Some(token) = self.peek() {
+            if token == Token::Close || stop(&token) {
+                return;
+            }
+            self.pos += 1;
+            self.translate_token(token, out);
+        }
+    }
+
+    /// After an embedded expression like `#tex-R()`, separate following text that would
+    /// continue it.
+    fn end_expression(&self, out: &mut String) {
+        if matches!(self.peek(), Some(Token::Char('(' | '[' | '.'))) {
+            out.push(' ');
+        }
+    }
+
+    /// Skip spaces, which LaTeX ignores before arguments.
+    fn skip_spaces(&mut self) {
+        while matches!(self.peek(), Some(Token::Char(c)) if c.is_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    /// Translate the next argument: a group, or a single token.
+    fn arg(&mut self) -> String {
+        self.skip_spaces();
+        let mut out = String::new();
+        self.nested += 1;
+        match self.next() {
+            Some(Token::Open) => {
+                self.translate_until(&mut out, |_| false);
+                self.pos += 1;
+            }
+            Some(token) => self.translate_token(token, &mut out),
+            None => {}
+        }
+        self.nested -= 1;
+        out.trim().to_string()
+    }
+
+    /// The next argument as plain text, for `\text` and the like.
+    fn text_arg(&mut self) -> String {
+        self.skip_spaces();
+        let start = self.pos;
+        let end = match self.peek() {
+            Some(Token::Open) => start + group_len(&self.tokens[start..]).unwrap_or(1),
+            Some(_) => start + 1,
+            None => start,
+        };
+        self.pos = end;
+        let tokens = match self.tokens.get(start) {
+            Some(Token::Open) if end - start >= 2 => &self.tokens[start + 1..end - 1],
+            _ => &self.tokens[start..end],
+        };
+        let mut text = String::new();
+        for token in tokens {
+            match token {
+                Token::Char(c) => text.push(*c),
+                Token::Command(c) if c.len() == 1 => text.push_str(c),
+                Token::Command(c) => text.push_str(symbol(c).unwrap_or(c)),
+                Token::Open | Token::Close => {}
+            }
+        }
+        text
+    }
+
+    /// An optional argument in brackets, like the index of `\sqrt[3]{x}`.
+    fn optional_arg(&mut self) -> Option<String> {
+        self.skip_spaces();
+        if self.peek() != Some(Token::Char('[')) {
+            return None;
+        }
+        self.pos += 1;
+        let mut out = String::new();
+        self.nested += 1;
+        self.translate_until(&mut out, |t| *t == Token::Char(']'));
+        self.nested -= 1;
+        self.pos += 1;
+        Some(out.trim().to_string())
+    }
+
+    fn translate_token(&mut self, token: Token<'a>, out: &mut String) {
+        match token {
+            Token::Char(c) if c.is_alphabetic() => {
+                // Separate letters, which would otherwise form an identifier.
+                separate(out);
+                out.push(c);
+            }
+            Token::Char(c @ ('^' | '_')) => {
+                out.push(c);
+                let arg = self.arg();
+                if arg.chars().count() == 1 || arg.chars().all(|c| c.is_alphanumeric()) {
+                    out.push_str(&arg);
+                } else {
+                    write!(out, "({})", arg).unwrap();
+                }
+            }
+            Token::Char('~') => out.push(' '),
+            Token::Char('#') if matches!(self.peek(), Some(Token::Char('1'..='9'))) => {
+                // A parameter in the body of a macro.
+                if let Some(Token::Char(n)) = self.next() {
+                    write!(out, "#(a{})", n).unwrap();
+                    self.end_expression(out);
+                }
+            }
+            Token::Char(c @ (',' | ';')) if self.nested > 0 => {
+                out.push('\\');
+                out.push(c);
+            }
+            Token::Char(c @ ('/' | '"' | '#' | '$' | '@')) => {
+                out.push('\\');
+                out.push(c);
+            }
+            Token::Char(c) => out.push(c),
+            Token::Open => {
+                self.translate_until(out, |_| false);
+                self.pos += 1;
+            }
+            // Unmatched; dropped.
+            Token::Close => {}
+            Token::Command(name) => self.translate_command(name, out),
+        }
+    }
+
+    fn translate_command(&mut self, name: &'a str, out: &mut String) {
+        if let Some(params) = self.macros.params(name) {
+            let args = (0..params)
+                .map(|_| format!("${}$", self.arg()))
+                .collect::<Vec<_>>();
+            write!(out, "#tex-{}({})", name, args.join(", ")).unwrap();
+            self.end_expression(out);
+            return;
+        }
+        if let Some(symbol) = symbol(name) {
+            separate(out);
+            out.push_str(symbol);
+            return;
+        }
+        if let Some(function) = function(name) {
+            separate(out);
+            write!(out, "{}({})", function, self.arg()).unwrap();
+            return;
+        }
+        match name {
+            "frac" | "dfrac" | "tfrac" | "cfrac" | "binom" => {
+                let (num, denom) = (self.arg(), self.arg());
+                let function = if name == "binom" { "binom" } else { "frac" };
+                separate(out);
+                write!(out, "{}({}, {})", function, num, denom).unwrap();
+            }
+            "sqrt" => {
+                separate(out);
+                match self.optional_arg() {
+                    Some(index) => write!(out, "root({}, {})", index, self.arg()).unwrap(),
+                    None => write!(out, "sqrt({})", self.arg()).unwrap(),
+                }
+            }
+            "text" | "textrm" | "textnormal" | "mbox" | "textit" | "textbf" | "mathrm" => {
+                let text = self.text_arg();
+                let text = typst_string(&text);
+                separate(out);
+                match name {
+                    "textit" => write!(out, "italic({})", text).unwrap(),
+                    "textbf" => write!(out, "bold({})", text).unwrap(),
+                    "mathrm" => write!(out, "upright({})", text).unwrap(),
+                    _ => out.push_str(&text),
+                }
+            }
+            "operatorname" => {
+                let text = self.text_arg();
+                separate(out);
+                write!(out, "op({})", typst_string(&text)).unwrap();
+            }
+            "left" | "right" | "bigl" | "bigr" | "Bigl" | "Bigr" | "big" | "Big" | "bigg"
+            | "Bigg" => {
+                // Typst scales matching delimiters by itself.
+                match self.next() {
+                    Some(Token::Char('.')) | None => {}
+                    Some(token) => self.translate_token(token, out),
+                }
+            }
+            "begin" => self.environment(out),
+            // Stray `\end`, dropped.
+            "end" => {
+                self.text_arg();
+            }
+            "\\" | "cr" => out.push_str(" \\ "),
+            "newcommand" | "renewcommand" | "providecommand" | "DeclareMathOperator" | "def" => {
+                // Definitions were collected beforehand.
+                self.pos -= 1;
+                if parse_definition(self.tokens, &mut self.pos).is_none() {
+                    self.pos += 1;
+                }
+            }
+            "displaystyle" | "textstyle" | "limits" | "nolimits" | "nonumber" | "notag" => {}
+            "label" | "tag" => {
+                self.text_arg();
+            }
+            _ => {
+                self.unknown.push(name.to_string());
+                separate(out);
+                out.push_str(&typst_string(&format!("\\{}", name)));
+            }
+        }
+    }
+
+    /// Translate the environment after `\begin`, up to its `\end`.
+    fn environment(&mut self, out: &mut String) {
+        let name = self.text_arg();
+        if name == "array" {
+            // Column specification.
+            self.text_arg();
+        }
+        let mut rows = vec![];
+        let mut row = vec![];
+        loop {
+            let mut cell = String::new();
+            self.nested += 1;
+            self.translate_until(&mut cell, |t| {
+                matches!(t, Token::Char('&') | Token::Command("\\" | "cr" | "end"))
+            });
+            self.nested -= 1;
+            row.push(cell.trim().to_string());
+            match self.next() {
+                Some(Token::Char('&')) => continue,
+                Some(Token::Command("\\" | "cr")) => rows.push(std::mem::take(&mut row)),
+                Some(Token::Command("end")) => {
+                    self.text_arg();
+                    break;
+                }
+                _ => break,
+            }
+        }
+        // `\\` after the last row leaves an empty one behind.
+        if row.iter().any(|c| !c.is_empty()) {
+            rows.push(row);
+        }
+
+        let delim = match name.as_str() {
+            "matrix" | "array" | "smallmatrix" => Some("#none"),
+            "pmatrix" => Some("\"(\""),
+            "bmatrix" => Some("\"[\""),
+            "Bmatrix" => Some("\"{\""),
+            "vmatrix" => Some("\"|\""),
+            "Vmatrix" => Some("\"||\""),
+            _ => None,
+        };
+        separate(out);
+        if let Some(delim) = delim {
+            let rows = rows.iter().map(|r| r.join(", ")).collect::<Vec<_>>();
+            write!(out, "mat(delim: {}, {})", delim, rows.join("; ")).unwrap();
+        } else if name == "cases" {
+            let rows = rows.iter().map(|r| r.join(" & ")).collect::<Vec<_>>();
+            write!(out, "cases({})", rows.join(", ")).unwrap();
+        } else {
+            // aligned, align, gather, split, ...: alignment points and line breaks.
+            let rows = rows.iter().map(|r| r.join(" & ")).collect::<Vec<_>>();
+            out.push_str(&rows.join(" \\ "));
+        }
+    }
+}
+
+/// Add a space if the last character would join with an identifier written next.
+fn separate(out: &mut String) {
+    if out.ends_with(|c: char| c.is_alphanumeric() || c == ')') {
+        out.push(' ');
+    }
+}
+
+/// Typst functions taking one argument, by LaTeX command.
+fn function(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "mathbb" => "bb",
+        "mathbf" | "boldsymbol" | "bm" => "bold",
+        "mathit" => "italic",
+        "mathcal" => "cal",
+        "mathfrak" => "frak",
+        "mathsf" => "sans",
+        "mathtt" => "mono",
+        "hat" | "widehat" => "hat",
+        "tilde" | "widetilde" => "tilde",
+        "bar" => "macron",
+        "overline" => "overline",
+        "underline" => "underline",
+        "vec" => "arrow",
+        "dot" => "dot",
+        "ddot" => "dot.double",
+        "overbrace" => "overbrace",
+        "underbrace" => "underbrace",
+        "cancel" => "cancel",
+        "abs" => "abs",
+        "norm" => "norm",
+        _ => return None,
+    })
+}
+
+/// Typst symbols, by LaTeX command.
+fn symbol(name: &str) -> Option<&'static str> {
+    const GREEK: &[&str] = &[
+        "alpha", "beta", "gamma", "delta", "zeta", "eta", "theta", "iota", "kappa", "lambda", "mu",
+        "nu", "xi", "pi", "rho", "sigma", "tau", "upsilon", "chi", "psi", "omega", "Gamma",
+        "Delta", "Theta", "Lambda", "Xi", "Pi", "Sigma", "Upsilon", "Phi", "Psi", "Omega",
+    ];
+    // Operators that are written the same in Typst.
+    const SAME: &[&str] = &[
+        "sin", "cos", "tan", "cot", "sec", "csc", "arcsin", "arccos", "arctan", "sinh", "cosh",
+        "tanh", "log", "ln", "lg", "exp", "lim", "limsup", "liminf", "max", "min", "sup", "inf",
+        "det", "dim", "ker", "deg", "gcd", "arg", "Pr", "mod", "sum", "nabla", "forall", "exists",
+        "emptyset", "approx", "equiv", "subset", "supset", "in", "ell", "quad", "dots", "top",
+        "bot", "times", "div", "star", "dagger", "aleph", "Re", "Im",
+    ];
+    if let Some(name) = GREEK.iter().chain(SAME).find(|n| **n == name) {
+        return Some(name);
+    }
+    Some(match name {
+        "epsilon" => "epsilon.alt",
+        "varepsilon" => "epsilon",
+        "phi" => "phi.alt",
+        "varphi" => "phi",
+        "vartheta" => "theta.alt",
+        "varpi" => "pi.alt",
+        "varrho" => "rho.alt",
+        "varsigma" => "sigma.alt",
+        "infty" => "infinity",
+        "partial" => "diff",
+        "prod" => "product",
+        "coprod" => "product.co",
+        "int" => "integral",
+        "iint" => "integral.double",
+        "iiint" => "integral.triple",
+        "oint" => "integral.cont",
+        "bigcup" => "union.big",
+        "bigcap" => "sect.big",
+        "cup" => "union",
+        "cap" => "sect",
+        "setminus" => "without",
+        "subseteq" => "subset.eq",
+        "supseteq" => "supset.eq",
+        "notin" => "in.not",
+        "ni" => "in.rev",
+        "cdot" => "dot.op",
+        "cdots" => "dots.h.c",
+        "ldots" => "dots.h",
+        "vdots" => "dots.v",
+        "ddots" => "dots.down",
+        "pm" => "plus.minus",
+        "mp" => "minus.plus",
+        "le" | "leq" => "<=",
+        "ge" | "geq" => ">=",
+        "ne" | "neq" => "!=",
+        "ll" => "<<",
+        "gg" => ">>",
+        "sim" => "tilde.op",
+        "simeq" => "tilde.eq",
+        "cong" => "tilde.equiv",
+        "propto" => "prop",
+        "to" | "rightarrow" => "->",
+        "leftarrow" | "gets" => "<-",
+        "leftrightarrow" => "<->",
+        "Rightarrow" | "implies" => "=>",
+        "Leftarrow" => "arrow.l.double",
+        "Leftrightarrow" | "iff" => "<=>",
+        "mapsto" => "|->",
+        "uparrow" => "arrow.t",
+        "downarrow" => "arrow.b",
+        "circ" => "compose",
+        "bullet" => "bullet",
+        "oplus" => "plus.circle",
+        "otimes" => "times.circle",
+        "wedge" | "land" => "and",
+        "vee" | "lor" => "or",
+        "neg" | "lnot" => "not",
+        "perp" => "perp",
+        "parallel" => "parallel",
+        "angle" => "angle",
+        "hbar" => "planck.reduce",
+        "prime" => "prime",
+        "langle" => "angle.l",
+        "rangle" => "angle.r",
+        "lfloor" => "floor.l",
+        "rfloor" => "floor.r",
+        "lceil" => "ceil.l",
+        "rceil" => "ceil.r",
+        "mid" | "vert" => "|",
+        "|" | "Vert" => "||",
+        "{" | "lbrace" => "{",
+        "}" | "rbrace" => "}",
+        "qquad" => "wide",
+        "," | "thinspace" => "thin",
+        ":" | ">" | "medspace" => "med",
+        ";" | "thickspace" => "thick",
+        " " => "space",
+        "!" => "",
+        "%" => "%",
+        "&" => "&",
+        "_" => "\\_",
+        "#" => "\\#",
+        "$" => "\\$",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn translate(latex: &str) -> String {
+        let (math, unknown) = latex_to_typst(latex, &Macros::default());
+        assert!(unknown.is_empty(), "unknown commands {:?}", unknown);
+        math.trim().to_string()
+    }
+
+    #[test]
+    fn fractions() {
+        assert_eq!(translate(r"\frac{a}{b}"), "frac(a, b)");
+        assert_eq!(translate(r"\frac12"), "frac(1, 2)");
+        assert_eq!(translate(r"\binom{n}{k}"), "binom(n, k)");
+        assert_eq!(translate(r"\frac{a,b}{c}"), r"frac(a\,b, c)");
+    }
+
+    #[test]
+    fn roots() {
+        assert_eq!(translate(r"\sqrt{x}"), "sqrt(x)");
+        assert_eq!(translate(r"\sqrt[3]{x}"), "root(3, x)");
+        assert_eq!(translate(r"\sqrt{x;y}"), r"sqrt(x\;y)");
+    }
+
+    #[test]
+    fn matrices() {
+        assert_eq!(
+            translate(r"\begin{pmatrix} a & b \\ c & d \end{pmatrix}"),
+            r#"mat(delim: "(", a, b; c, d)"#
+        );
+        assert_eq!(
+            translate(r"\begin{bmatrix} a,b & c \end{bmatrix}"),
+            r#"mat(delim: "[", a\,b, c)"#
+        );
+    }
+
+    #[test]
+    fn cases() {
+        assert_eq!(
+            translate(r"\begin{cases} 1 & x > 0 \\ 0 & \text{otherwise} \end{cases}"),
+            r#"cases(1 & x > 0, 0 & "otherwise")"#
+        );
+    }
+
+    #[test]
+    fn top_level_commas() {
+        assert_eq!(translate("f(a,b)"), "f(a,b)");
+    }
+}
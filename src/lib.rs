@@ -0,0 +1,230 @@
+//! Convert Jupyter notebooks into Typst source code.
+//!
+//! ```no_run
+//! use jupyter2typst::{Converter, Options};
+//!
+//! let typst = Converter::new(Options::default()).convert_file("analysis.ipynb")?;
+//! # Ok::<(), jupyter2typst::J2TError>(())
+//! ```
+
+#[macro_use]
+pub mod log;
+
+mod assets;
+mod cache;
+mod cell;
+mod check;
+mod citations;
+#[cfg(feature = "pdf")]
+mod compile;
+pub mod config;
+mod deflist;
+pub mod diagnostics;
+mod document;
+mod emoji;
+mod filter;
+mod jupytext;
+mod latex;
+mod markdown;
+mod math;
+mod myst;
+mod notebook;
+mod output;
+mod quarto;
+#[cfg(feature = "remote")]
+mod remote;
+pub mod render;
+pub mod server;
+mod split;
+mod tableize;
+mod templates;
+mod typography;
+mod upgrade;
+#[cfg(feature = "wasm")]
+mod wasm;
+mod yaml;
+
+pub use cell::{NbgraderMode, Prompts};
+pub use config::{Options, TagAction};
+pub use diagnostics::Diagnostic;
+pub use document::{
+    format_timestamp, typst_color, typst_length, typst_stroke, BlockStyle, Date, DocumentOverrides,
+    Layout, PageSetup,
+};
+pub use notebook::{is_notebook_path, is_remote};
+pub use output::Wrap;
+
+use assets::Assets;
+
+use std::error::Error;
+use std::io;
+use std::path::Path;
+
+/// File name standing for stdin or stdout.
+pub const STDIO_PATH: &str = "-";
+
+#[derive(Debug, Default)]
+pub enum J2TErrorKind {
+    Json(tinyjson::UnexpectedValue),
+    Md(String),
+    Io(io::Error),
+    Template(ramhorns::Error),
+    JsonParse(tinyjson::JsonParseError),
+    #[default]
+    Unknown,
+}
+
+#[derive(Debug, Default)]
+pub struct J2TError {
+    pub kind: J2TErrorKind,
+    pub msg: Option<String>,
+    /// Index of the cell the error occurred in.
+    pub cell: Option<usize>,
+}
+
+impl std::fmt::Display for J2TError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        if let Some(cell) = self.cell {
+            write!(f, "cell {}: ", cell)?;
+        }
+        let cause = match self.kind {
+            J2TErrorKind::Json(ref e) => Some(e.to_string()),
+            J2TErrorKind::Md(ref e) => Some(e.clone()),
+            J2TErrorKind::Io(ref e) => Some(e.to_string()),
+            J2TErrorKind::Template(ref e) => Some(e.to_string()),
+            J2TErrorKind::JsonParse(ref e) => Some(e.to_string()),
+            J2TErrorKind::Unknown => None,
+        };
+        match (self.msg.as_deref(), cause) {
+            (Some(msg), Some(cause)) => write!(f, "{}: {}", msg, cause),
+            (Some(msg), None) => write!(f, "{}", msg),
+            (None, Some(cause)) => write!(f, "{}", cause),
+            (None, None) => write!(f, "unknown error"),
+        }
+    }
+}
+
+impl Error for J2TError {}
+
+impl J2TError {
+    /// The process exit status for a conversion failing with this error.
+    pub fn exit_code(&self) -> i32 {
+        match self.kind {
+            J2TErrorKind::JsonParse(_) => diagnostics::EXIT_PARSE,
+            J2TErrorKind::Io(_) | J2TErrorKind::Template(_) => diagnostics::EXIT_USAGE,
+            _ => diagnostics::EXIT_CONVERSION,
+        }
+    }
+}
+
+impl From<String> for J2TError {
+    fn from(s: String) -> J2TError {
+        J2TError {
+            kind: J2TErrorKind::Md(s),
+            ..Default::default()
+        }
+    }
+}
+
+impl From<tinyjson::UnexpectedValue> for J2TError {
+    fn from(s: tinyjson::UnexpectedValue) -> J2TError {
+        J2TError {
+            kind: J2TErrorKind::Json(s),
+            ..Default::default()
+        }
+    }
+}
+
+impl From<std::fmt::Error> for J2TError {
+    fn from(_: std::fmt::Error) -> J2TError {
+        J2TError {
+            msg: Some("formatting failed".to_string()),
+            ..Default::default()
+        }
+    }
+}
+
+impl From<io::Error> for J2TError {
+    fn from(s: io::Error) -> J2TError {
+        J2TError {
+            kind: J2TErrorKind::Io(s),
+            ..Default::default()
+        }
+    }
+}
+
+impl From<tinyjson::JsonParseError> for J2TError {
+    fn from(s: tinyjson::JsonParseError) -> J2TError {
+        J2TError {
+            kind: J2TErrorKind::JsonParse(s),
+            ..Default::default()
+        }
+    }
+}
+
+impl From<ramhorns::Error> for J2TError {
+    fn from(s: ramhorns::Error) -> J2TError {
+        J2TError {
+            kind: J2TErrorKind::Template(s),
+            ..Default::default()
+        }
+    }
+}
+
+/// Converts notebooks into Typst source, embeddable into other tools.
+///
+/// Extracted images are written into `Options::assets_dir`, by default `<notebook>_assets` in
+/// the working directory.
+pub struct Converter {
+    opts: Options,
+    overrides: DocumentOverrides,
+    write_assets: bool,
+}
+
+impl Converter {
+    pub fn new(opts: Options) -> Converter {
+        Converter {
+            opts,
+            overrides: DocumentOverrides::default(),
+            write_assets: true,
+        }
+    }
+
+    /// Don't write extracted images, only refer to them where they would be stored. For
+    /// environments without a file system.
+    pub fn without_assets(self) -> Converter {
+        Converter {
+            write_assets: false,
+            ..self
+        }
+    }
+
+    /// Use the given title, authors or date instead of those from the notebook metadata.
+    pub fn with_overrides(self, overrides: DocumentOverrides) -> Converter {
+        Converter { overrides, ..self }
+    }
+
+    /// Convert the notebook JSON `source`.
+    pub fn convert_str(&self, source: &str) -> Result<String, J2TError> {
+        self.convert(notebook::parse_notebook(source)?, STDIO_PATH)
+    }
+
+    /// Convert the notebook file `path`.
+    pub fn convert_file<P: AsRef<Path>>(&self, path: P) -> Result<String, J2TError> {
+        let infile = path.as_ref().to_string_lossy();
+        self.convert(notebook::parse_notebook_file(path.as_ref())?, &infile)
+    }
+
+    fn convert(&self, nb: tinyjson::JsonValue, infile: &str) -> Result<String, J2TError> {
+        let nb = render::load_notebook(&self.opts, nb)?;
+        let assets = if self.write_assets {
+            Assets::new(self.opts.assets_dir.as_deref(), infile, STDIO_PATH)
+        } else {
+            Assets::dry_run(self.opts.assets_dir.as_deref(), infile)
+        };
+        let mut out = vec![];
+        render::render_notebook(&self.opts, nb, infile, &assets, &self.overrides, &mut out)?;
+        let out = render::post_filter(&self.opts, out)?;
+        String::from_utf8(out).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e).into())
+    }
+}
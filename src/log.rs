@@ -0,0 +1,118 @@
+//! Leveled diagnostics on stderr, keeping stdout free for piped output.
+
+use std::io::{self, IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error = 0,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+static PROGRESS: AtomicBool = AtomicBool::new(false);
+
+pub fn set_level(level: Level) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn enabled(level: Level) -> bool {
+    level as u8 <= LEVEL.load(Ordering::Relaxed)
+}
+
+/// Enable the per-cell progress bar. It is only shown if stderr is a terminal.
+pub fn set_progress(enabled: bool) {
+    PROGRESS.store(enabled && io::stderr().is_terminal(), Ordering::Relaxed);
+}
+
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)*) => {
+        if $crate::log::enabled($crate::log::Level::Error) {
+            eprintln!("error: {}", format_args!($($arg)*));
+        }
+    };
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)*) => {
+        if $crate::log::enabled($crate::log::Level::Warn) {
+            eprintln!("warning: {}", format_args!($($arg)*));
+        }
+    };
+}
+
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => {
+        if $crate::log::enabled($crate::log::Level::Info) {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        if $crate::log::enabled($crate::log::Level::Debug) {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)*) => {
+        if $crate::log::enabled($crate::log::Level::Trace) {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+/// A progress bar counting converted cells.
+pub struct Progress {
+    total: usize,
+    done: AtomicUsize,
+    enabled: bool,
+}
+
+const PROGRESS_WIDTH: usize = 30;
+
+impl Progress {
+    pub fn new(total: usize) -> Progress {
+        Progress {
+            total,
+            done: AtomicUsize::new(0),
+            enabled: PROGRESS.load(Ordering::Relaxed) && total > 0,
+        }
+    }
+
+    /// Count a converted cell. May be called from several threads.
+    pub fn tick(&self) {
+        let done = self.done.fetch_add(1, Ordering::Relaxed) + 1;
+        if !self.enabled {
+            return;
+        }
+        let filled = PROGRESS_WIDTH * done / self.total;
+        eprint!(
+            "\r[{}{}] {}/{} cells",
+            "#".repeat(filled),
+            " ".repeat(PROGRESS_WIDTH - filled),
+            done,
+            self.total
+        );
+        io::stderr().flush().ok();
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        if self.enabled {
+            eprintln!();
+        }
+    }
+}
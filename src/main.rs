@@ -1,336 +1,520 @@
-use markdown::mdast::Node;
+use jupyter2typst::config::{flag, Options};
+use jupyter2typst::diagnostics::{self, Diagnostic, Severity};
+use jupyter2typst::render::{check_notebook, convert_notebook, merge_notebooks};
+use jupyter2typst::server;
+use jupyter2typst::{
+    error, format_timestamp, info, is_notebook_path, is_remote, log, typst_color, typst_length,
+    typst_stroke, DocumentOverrides, J2TError, STDIO_PATH,
+};
+
 use rustop::opts;
-use tinyjson::JsonValue;
 
-use std::collections::{
-    hash_map::{Entry, OccupiedEntry},
-    HashMap,
-};
-use std::error::Error;
-use std::fmt::{self, Write};
-use std::io;
-use std::ops::Deref;
-use std::{fs, path::Path};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
 
-struct Context {
-    verbose: bool,
-    lang: String,
+/// Print `msg` and exit with an error status.
+fn fail<D: fmt::Display>(msg: D) -> ! {
+    error!("{}", msg);
+    std::process::exit(1);
 }
 
-fn notebook_overview(ctx: &Context, nb: &JsonValue) {
-    match nb {
-        JsonValue::Object(ref hm) => {
-            if ctx.verbose {
-                println!("Notebook with keys {:?}", hm.keys());
-                println!(
-                    "Version: {}.{}",
-                    hm["nbformat"].format().unwrap(),
-                    hm["nbformat_minor"].format().unwrap()
-                );
-                println!("=> Well-formed!");
+fn main() {
+    let (args, _rest) = opts! {
+        synopsis "Convert a jupyter notebook into typst source code.";
+        opt quiet:bool, short:'q', desc:"Only report errors";
+        opt verbose:bool, short:'v', desc:"Report details about the conversion; -vv reports everything, including the parsed Markdown syntax trees";
+        opt progress:bool, desc:"Show a progress bar while converting cells";
+        opt config:Option<String>, desc:"Configuration file (default: jupyter2typst.toml next to the notebook)";
+        opt skip_unexecuted:bool, desc:"Omit the result block of cells that were never executed";
+        opt no_skip_unexecuted:bool, desc:"Turn off --skip-unexecuted, e.g. when set in the configuration file";
+        opt strict:bool, desc:"Abort on the first construct that can't be converted, instead of skipping it";
+        opt no_strict:bool, desc:"Turn off --strict, e.g. when set in the configuration file";
+        opt respect_collapse:bool, desc:"Hide code and outputs collapsed in the notebook";
+        opt no_respect_collapse:bool, desc:"Turn off --respect-collapse, e.g. when set in the configuration file";
+        opt prompts:Option<String>, desc:"Execution count prompts: in-out, numbers (default) or none";
+        opt line_numbers:bool, desc:"Number the lines of each code cell";
+        opt no_line_numbers:bool, desc:"Turn off --line-numbers, e.g. when set in the configuration file";
+        opt wrap_code:Option<String>, desc:"Long code lines: soft, hard:N (break at column N) or off (default)";
+        opt wrap_output:Option<String>, desc:"Long output lines: soft, hard:N (break at column N) or off (default)";
+        opt nbgrader:Option<String>, desc:"Render an nbgrader assignment: student or solution";
+        opt title:Option<String>, desc:"Document title (default: notebook metadata or file name)";
+        opt author:Option<String>, desc:"Comma-separated list of authors";
+        opt date:Option<String>, desc:"Document date as YYYY-MM-DD (default: metadata or file modification date)";
+        opt template:Option<String>, desc:"Typst template file replacing the built-in definitions";
+        opt lang:Option<String>, desc:"Language of code cells (default: the notebook's kernel language)";
+        opt lang_map:Option<String>, desc:"Comma-separated highlighting languages by kernel language, e.g. ir=r,sagemath=python";
+        opt theme:Option<String>, desc:"Document theme: default, minimal, dark or report";
+        opt paper:Option<String>, desc:"Paper size, e.g. a4 (default) or us-letter";
+        opt margin:Option<String>, desc:"Page margin, e.g. 2cm";
+        opt font:Option<String>, desc:"Body text font";
+        opt font_size:Option<String>, desc:"Body text font size, e.g. 11pt";
+        opt code_font_size:Option<String>, desc:"Font size of code and results, e.g. 9pt";
+        opt code_bg:Option<String>, desc:"Background of code blocks, e.g. #f0f0f0 or luma(240)";
+        opt result_bg:Option<String>, desc:"Background of result blocks";
+        opt result_stroke:Option<String>, desc:"Border of result blocks, e.g. 1pt + gray or none";
+        opt block_radius:Option<String>, desc:"Corner radius of code and result blocks, e.g. 3pt";
+        opt toc:bool, desc:"Insert a table of contents after the title";
+        opt no_toc:bool, desc:"Turn off --toc, e.g. when set in the configuration file";
+        opt toc_depth:Option<usize>, desc:"Maximum heading level shown in the table of contents (default: 3)";
+        opt smartquotes:bool, desc:"Use typographic quotes, dashes and ellipses in Markdown text";
+        opt no_smartquotes:bool, desc:"Turn off --smartquotes, e.g. when set in the configuration file";
+        opt emoji_font:Option<String>, desc:"Font for emoji in Markdown text, e.g. \"Noto Color Emoji\"";
+        opt tableize_output:bool, desc:"Show text outputs that look like pandas or polars DataFrames as tables";
+        opt no_tableize_output:bool, desc:"Turn off --tableize-output, e.g. when set in the configuration file";
+        opt layout:Option<String>, desc:"Page layout: onecol (default) or twocol";
+        opt split_by_heading:Option<usize>, desc:"Write a chapter file per Markdown heading up to this level, included by the output file";
+        opt break_before_headings:Option<String>, desc:"Comma-separated levels of Markdown headings to start a new page before, e.g. 1,2";
+        opt mime_priority:Option<String>, desc:"Comma-separated MIME types of outputs to render, most preferred first";
+        opt max_output_lines:Option<usize>, desc:"Truncate outputs longer than this many lines";
+        opt pdf:bool, desc:"Compile to PDF (or SVG, by output file extension) instead of writing Typst source";
+        opt no_pdf:bool, desc:"Turn off --pdf, e.g. when set in the configuration file";
+        opt check:bool, desc:"Only validate and test-convert the input files, writing nothing";
+        opt watch:bool, desc:"Convert again whenever the notebook, template or configuration changes";
+        opt out_dir:Option<String>, desc:"Write <notebook>.typ files into this directory";
+        opt merge:bool, desc:"Combine all input notebooks into one document, written to the last file given";
+        opt assets_dir:Option<String>, desc:"Directory for extracted images (default: <outfile>_assets)";
+        opt cache:bool, desc:"Reuse cells converted by earlier runs, cached in .jupyter2typst-cache";
+        opt no_cache:bool, desc:"Turn off --cache, e.g. when set in the configuration file";
+        opt bib:Option<String>, desc:"Bibliography (BibTeX or Hayagriva YAML) for [@key] citations, appended to the document";
+        opt math_macros:Option<String>, desc:"JSON file mapping LaTeX macro names to their definitions, for math in Markdown";
+        opt report:Option<String>, desc:"Write all warnings and errors as JSON into this file, and exit with status 4 on warnings";
+        opt serve:Option<String>, desc:"Convert notebooks posted to http://<address>/convert, e.g. 127.0.0.1:8700";
+        opt serve_public:bool, desc:"Allow --serve to listen on addresses other than loopback, reachable from other machines";
+        opt cell_filter:Option<String>, desc:"Shell command each cell's JSON is piped through before conversion";
+        opt post_filter:Option<String>, desc:"Shell command the generated Typst source is piped through";
+        opt token:Option<String>, desc:"Token for jupyter://host/api/contents/... inputs (default: $JUPYTER_TOKEN)";
+        param paths:Vec<String>, desc:"Input file(s), URLs or glob patterns, optionally followed by the output file (- for stdin/stdout)";
+    }
+    .parse_or_exit();
 
-                let md: HashMap<_, _> = hm["metadata"].clone().try_into().unwrap();
-                println!("Language: {}", md["kernelspec"].format().unwrap());
+    let verbosity = verbosity();
+    // `verbose = true` in a configuration file counts like a single `-v`.
+    let log_level = |verbose_config: bool| {
+        if verbosity >= 2 {
+            log::Level::Trace
+        } else if verbosity == 1 || (verbose_config && !args.quiet) {
+            log::Level::Debug
+        } else if args.quiet {
+            log::Level::Error
+        } else {
+            log::Level::Info
+        }
+    };
+    log::set_level(log_level(false));
+    log::set_progress(args.progress && !args.quiet);
+
+    let length = |l: &str| typst_length(l).unwrap_or_else(|e| fail(e));
+    // Command line flags take precedence over the configuration file, which may differ between
+    // notebooks in different directories.
+    let load_options = |infile: &str| -> Result<Options, J2TError> {
+        let mut opts = Options::load(args.config.as_deref(), infile)?;
+        if opts.verbose {
+            log::set_level(log_level(true));
+        }
+        opts.skip_unexecuted = flag(
+            opts.skip_unexecuted,
+            args.skip_unexecuted,
+            args.no_skip_unexecuted,
+        );
+        opts.respect_collapse = flag(
+            opts.respect_collapse,
+            args.respect_collapse,
+            args.no_respect_collapse,
+        );
+        opts.line_numbers = flag(opts.line_numbers, args.line_numbers, args.no_line_numbers);
+        opts.tableize_output = flag(
+            opts.tableize_output,
+            args.tableize_output,
+            args.no_tableize_output,
+        );
+        opts.smartquotes = flag(opts.smartquotes, args.smartquotes, args.no_smartquotes);
+        opts.toc = flag(opts.toc, args.toc, args.no_toc);
+        opts.compile = flag(opts.compile, args.pdf, args.no_pdf);
+        opts.strict = flag(opts.strict, args.strict, args.no_strict);
+        opts.cache = flag(opts.cache, args.cache, args.no_cache);
+        if let Some(ref prompts) = args.prompts {
+            opts.prompts = prompts.parse().unwrap_or_else(|e: String| fail(e));
+        }
+        if let Some(ref wrap) = args.wrap_code {
+            opts.wrap_code = wrap.parse().unwrap_or_else(|e: String| fail(e));
+        }
+        if let Some(ref wrap) = args.wrap_output {
+            opts.wrap_output = wrap.parse().unwrap_or_else(|e: String| fail(e));
+        }
+        if let Some(ref nbgrader) = args.nbgrader {
+            opts.nbgrader = Some(nbgrader.parse().unwrap_or_else(|e: String| fail(e)));
+        }
+        if args.template.is_some() {
+            opts.template = args.template.clone();
+        }
+        if args.lang.is_some() {
+            opts.lang = args.lang.clone();
+        }
+        if let Some(ref map) = args.lang_map {
+            for entry in map.split(',').filter(|e| !e.trim().is_empty()) {
+                match entry.split_once('=') {
+                    Some((kernel, lang)) => {
+                        let kernel = kernel.trim().to_lowercase();
+                        opts.lang_map.insert(kernel, lang.trim().to_string());
+                    }
+                    None => fail(format!(
+                        "invalid --lang-map entry '{}' (expected kernel=lang)",
+                        entry
+                    )),
+                }
             }
         }
-        _ => {
-            println!("Unknown notebook format!");
+        if let Some(ref theme) = args.theme {
+            opts.theme = theme.clone();
+        }
+        if let Some(ref paper) = args.paper {
+            opts.page.paper = paper.clone();
+        }
+        if let Some(ref margin) = args.margin {
+            opts.page.margin = Some(length(margin));
+        }
+        if args.font.is_some() {
+            opts.page.font = args.font.clone();
+        }
+        if let Some(ref size) = args.font_size {
+            opts.page.font_size = Some(length(size));
+        }
+        if let Some(ref size) = args.code_font_size {
+            opts.page.code_font_size = Some(length(size));
+        }
+        if let Some(ref color) = args.code_bg {
+            opts.style.code_bg = Some(typst_color(color).unwrap_or_else(|e| fail(e)));
+        }
+        if let Some(ref color) = args.result_bg {
+            opts.style.result_bg = Some(typst_color(color).unwrap_or_else(|e| fail(e)));
+        }
+        if let Some(ref stroke) = args.result_stroke {
+            opts.style.result_stroke = Some(typst_stroke(stroke).unwrap_or_else(|e| fail(e)));
+        }
+        if let Some(ref radius) = args.block_radius {
+            opts.style.block_radius = Some(length(radius));
         }
+        if let Some(depth) = args.toc_depth {
+            opts.toc_depth = depth;
+        }
+        if args.emoji_font.is_some() {
+            opts.emoji_font = args.emoji_font.clone();
+        }
+        if let Some(ref layout) = args.layout {
+            opts.layout = layout.parse().unwrap_or_else(|e: String| fail(e));
+        }
+        if args.split_by_heading.is_some() {
+            opts.split_by_heading = args.split_by_heading;
+        }
+        if let Some(ref levels) = args.break_before_headings {
+            opts.break_before_headings = levels
+                .split(',')
+                .filter(|l| !l.trim().is_empty())
+                .map(|l| {
+                    l.trim().parse().unwrap_or_else(|_| {
+                        fail(format!(
+                            "invalid heading level '{}' in --break-before-headings",
+                            l
+                        ))
+                    })
+                })
+                .collect();
+        }
+        if let Some(ref mimes) = args.mime_priority {
+            opts.mime_priority = mimes.split(',').map(|m| m.trim().to_string()).collect();
+        }
+        if args.max_output_lines.is_some() {
+            opts.max_output_lines = args.max_output_lines;
+        }
+        if args.assets_dir.is_some() {
+            opts.assets_dir = args.assets_dir.clone();
+        }
+        if args.bib.is_some() {
+            opts.bibliography = args.bib.clone();
+        }
+        if args.math_macros.is_some() {
+            opts.math_macros = args.math_macros.clone();
+        }
+        if args.cell_filter.is_some() {
+            opts.cell_filter = args.cell_filter.clone();
+        }
+        if args.post_filter.is_some() {
+            opts.post_filter = args.post_filter.clone();
+        }
+        opts.token = args
+            .token
+            .clone()
+            .or_else(|| std::env::var("JUPYTER_TOKEN").ok());
+        Ok(opts)
     };
-}
-
-fn parse_notebook_file<S: AsRef<Path>>(filename: S) -> JsonValue {
-    let file = fs::read(filename).unwrap();
-    let val: JsonValue = String::from_utf8(file).unwrap().parse().unwrap();
-    val
-}
 
-const document_root: &str = r###"
-#let input_notebook = "database_and_analysis.ipynb"
-
-#let sanitize_markdown(md) = md.replace("#", "=").replace("= ", "=")
-
-#let bgcolor_code = luma(230)
-#let bgcolor_result = rgb("a7d1de")
-#let codeblock(
-    lang: "python",
-    bgcolor: luma(230),
-    code) = block(fill: bgcolor,
-                  outset: 5pt,
-                  radius: 3pt,
-                  width: 100%,
-                  raw(code, lang: lang))
-#let resultblock(bgcolor: white, stroke: 1pt + luma(150), content) = [
-    #move(
-        align(
-            right, box(
-                inset: 0pt, height: 0pt, 
-                text(size: 10pt, fill: luma(140))[_Result:_])),
-            dx: -4em, dy: 12pt)
-    #block(fill: bgcolor, outset: 5pt, radius: 3pt, width: 100%, stroke: stroke, raw(content))
-]
-
-
-"###;
-
-#[derive(Debug, Default)]
-enum J2TErrorKind {
-    Json(tinyjson::UnexpectedValue),
-    Md(String),
-    Io(io::Error),
-    #[default]
-    Unknown,
-}
-
-#[derive(Debug, Default)]
-struct J2TError {
-    kind: J2TErrorKind,
-    msg: Option<String>,
-}
+    let overrides = DocumentOverrides {
+        title: args.title.clone(),
+        authors: args
+            .author
+            .as_ref()
+            .map(|a| a.split(',').map(|a| a.trim().to_string()).collect()),
+        date: args
+            .date
+            .as_ref()
+            .map(|d| d.parse().unwrap_or_else(|e: String| fail(e))),
+    };
 
-impl std::fmt::Display for J2TError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
-        f.write_fmt(format_args!(
-            "{} ({:?})",
-            self.msg.as_deref().unwrap_or(""),
-            self.kind
-        ))
+    if let Some(ref addr) = args.serve {
+        // Report invalid flags now rather than on the first request.
+        load_options(STDIO_PATH).unwrap_or_else(|e| fail(e));
+        let server_options = || load_options(STDIO_PATH);
+        if let Err(e) = server::serve(addr, args.serve_public, &server_options, &overrides) {
+            fail(e);
+        }
+        return;
     }
-}
 
-impl Error for J2TError {}
-
-impl From<String> for J2TError {
-    fn from(s: String) -> J2TError {
-        J2TError {
-            kind: J2TErrorKind::Md(s),
-            ..Default::default()
+    let mut diagnostics = vec![];
+    // Exit status of the most severe failure.
+    let mut status = None;
+    if args.check {
+        let inputs = expand_globs(&args.paths).unwrap_or_else(|e| fail(e));
+        for infile in inputs.iter() {
+            let (found, error) = check_notebook(load_options(infile), infile, &overrides);
+            if error.is_none() {
+                info!("{}: ok", infile);
+            }
+            for d in found.iter().filter(|d| d.severity == Severity::Error) {
+                error!("{}", d);
+            }
+            status = status.max(error);
+            diagnostics.extend(found);
         }
+        finish(args.report.as_deref(), &diagnostics, status);
     }
-}
-impl From<tinyjson::UnexpectedValue> for J2TError {
-    fn from(s: tinyjson::UnexpectedValue) -> J2TError {
-        J2TError {
-            kind: J2TErrorKind::Json(s),
-            ..Default::default()
+
+    if args.merge {
+        if args.watch {
+            fail("--merge cannot be used with --watch");
         }
-    }
-}
-impl From<io::Error> for J2TError {
-    fn from(s: io::Error) -> J2TError {
-        J2TError {
-            kind: J2TErrorKind::Io(s),
-            ..Default::default()
+        // The output file is split off before expanding patterns, so that a pattern can't make a
+        // notebook the output.
+        let (outfile, inputs) = match args.paths.split_last() {
+            Some((outfile, inputs)) if !inputs.is_empty() && !is_notebook_path(outfile) => {
+                (outfile, inputs)
+            }
+            _ => fail("--merge requires the input notebooks followed by the output file"),
+        };
+        let inputs = expand_globs(inputs).unwrap_or_else(|e| fail(e));
+        match load_options(&inputs[0])
+            .and_then(|opts| merge_notebooks(opts, &inputs, outfile, &overrides))
+        {
+            Ok(warnings) => diagnostics.extend(warnings),
+            Err(e) => {
+                error!("{}: {}", outfile, e);
+                status = Some(e.exit_code());
+                diagnostics.push(Diagnostic::error(outfile, e.to_string()));
+            }
         }
+        finish(args.report.as_deref(), &diagnostics, status);
     }
-}
 
-fn markdown_to_typst(n: &Node, out: &mut dyn Write) -> Result<(), J2TError> {
-    match n {
-        // TODO: implement markdown-to-typst translation
-        Node::Root(ref r) => {
-            r.children
-                .iter()
-                .map(|n2| markdown_to_typst(n2, out))
-                .for_each(drop);
-        }
-        Node::InlineCode(ref ic) => {
-            write!(out, "`{}`", ic.value).expect("write!()");
-        }
-        Node::Heading(ref h) => {
-            write!(
-                out,
-                "{} ",
-                std::iter::repeat("=")
-                    .take(h.depth as usize)
-                    .collect::<Vec<&str>>()
-                    .join("")
-            )
-            .expect("write!()");
-            h.children
-                .iter()
-                .map(|n2| markdown_to_typst(n2, out))
-                .for_each(drop);
-            out.write_str("\n\n").expect("write_str()");
-        }
-        Node::Paragraph(ref p) => {
-            p.children
-                .iter()
-                .map(|n2| markdown_to_typst(n2, out))
-                .for_each(drop);
-            out.write_str("\n").expect("write_str()");
-        }
-        Node::Text(ref t) => {
-            out.write_str(t.value.as_str()).expect("write_str()");
-        }
-        Node::Code(ref c) => {
-            write!(
-                out,
-                "```{}\n",
-                c.lang.as_ref().map(String::as_str).unwrap_or("")
-            );
-            out.write_str(c.value.as_str());
-            out.write_str("```\n");
+    let jobs = expand_globs(&args.paths)
+        .and_then(|paths| {
+            // `pdf` may also be set in the configuration file of each notebook.
+            let extension = |infile: &str| match load_options(infile) {
+                Ok(Options { compile: true, .. }) => "pdf",
+                _ => "typ",
+            };
+            plan_jobs(paths, args.out_dir.as_deref(), &extension)
+        })
+        .unwrap_or_else(|e| fail(e));
+    if args.watch {
+        if jobs.iter().any(|(infile, _)| infile == STDIO_PATH) {
+            fail("--watch cannot be used with stdin");
         }
-        _ => (),
-    }
-    Ok(())
-}
-
-fn convert_markdown_to_typst(s: &str) -> Result<String, J2TError> {
-    let po = markdown::ParseOptions::default();
-    let ast = markdown::to_mdast(s, &po)?;
-    println!("{:?}", ast);
-    let mut s = String::new();
-    markdown_to_typst(&ast, &mut s).expect("markdown_to_typst():");
-    Ok(s)
-}
-
-fn format_cell_result(
-    ctx: &Context,
-    cell: &HashMap<String, JsonValue>,
-) -> Result<String, J2TError> {
-    let content = Vec::<JsonValue>::try_from(cell["outputs"].clone())?;
-
-    let get_type = |output: &JsonValue| {
-        let o =
-            HashMap::<String, JsonValue>::try_from(output.clone()).expect("output object in cell");
-        (
-            o.get("output_type")
-                .map(|oo| String::try_from(oo.clone()).expect("output type is string"))
-                .clone(),
-            o.get("text").map(|e| e.clone()),
-            o.get("data").map(|e| e.clone()),
-        )
-    };
-    let kinds = content.iter().map(get_type).collect::<Vec<_>>();
-
-    // Prefer execute_result - text/plain. Then use stream / stderr.
-    let execute_result_str = "execute_result".to_string();
-    let stream_str = "stream".to_string();
-
-    // Weird ordering: we want to prefer `execute_result` over `stream`.
-    for result_type in &[execute_result_str, stream_str] {
-        for k in kinds.iter() {
-            if k.0.as_ref() == Some(result_type) {
-                // Type `data`
-                if let Some(ref data_obj) = k.2 {
-                    let data = HashMap::<_, _>::try_from(data_obj.clone())
-                        .expect("jsonvalue to hashmap failed for parsing cell output data");
-                    if let Some(e) = data.get("text/plain") {
-                        return Ok(strip_ansi_codes(join_json_lines_array(e.clone())));
-                    } // To do: other MIME types such as text/html and text/latex might typically be
-                      // available.
+        if jobs.iter().any(|(infile, _)| is_remote(infile)) {
+            fail("--watch cannot be used with URLs");
+        }
+        let mut last_seen = vec![None; jobs.len()];
+        loop {
+            for ((infile, outfile), seen) in jobs.iter().zip(last_seen.iter_mut()) {
+                let opts = load_options(infile);
+                let mut deps = vec![PathBuf::from(infile)];
+                deps.extend(Options::config_path(args.config.as_deref(), infile));
+                if let Ok(Options {
+                    template: Some(ref template),
+                    ..
+                }) = opts
+                {
+                    deps.push(PathBuf::from(template));
                 }
+                let mtimes = deps
+                    .iter()
+                    .map(|d| fs::metadata(d).and_then(|m| m.modified()).ok())
+                    .collect::<Vec<_>>();
+                if seen.as_ref() == Some(&mtimes) {
+                    continue;
+                }
+                *seen = Some(mtimes);
 
-                // Type `stream`
-                if let Some(ref text) = k.1 {
-                    return Ok(strip_ansi_codes(join_json_lines_array(text.clone())));
+                let now = format_timestamp(SystemTime::now());
+                match opts.and_then(|opts| convert_notebook(opts, infile, outfile, &overrides)) {
+                    Ok(_) => info!("[{}] {} -> {}", now, infile, outfile),
+                    Err(e) => error!("[{}] {}: {}", now, infile, e),
                 }
             }
+            std::thread::sleep(WATCH_INTERVAL);
         }
     }
 
-    Ok(String::new())
+    let mut failures = vec![];
+    for (infile, outfile) in jobs.iter() {
+        match load_options(infile)
+            .and_then(|opts| convert_notebook(opts, infile, outfile, &overrides))
+        {
+            Ok(warnings) => {
+                if jobs.len() > 1 {
+                    info!("{} -> {}", infile, outfile);
+                }
+                diagnostics.extend(warnings);
+            }
+            Err(e) => {
+                error!("{}: {}", infile, e);
+                status = status.max(Some(e.exit_code()));
+                diagnostics.push(Diagnostic::error(infile, e.to_string()));
+                failures.push(infile);
+            }
+        }
+    }
+    if jobs.len() > 1 {
+        info!(
+            "Converted {} of {} notebooks.",
+            jobs.len() - failures.len(),
+            jobs.len()
+        );
+        if !failures.is_empty() {
+            error!(
+                "Failed: {}",
+                failures
+                    .iter()
+                    .map(|f| f.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+    }
+    finish(args.report.as_deref(), &diagnostics, status);
 }
 
-fn strip_ansi_codes(s: String) -> String {
-    // TODO: implement this functionality.
-    s
+/// How often `-v` or `--verbose` was given, counting `-vv` twice.
+fn verbosity() -> usize {
+    std::env::args()
+        .skip(1)
+        .take_while(|a| a != "--")
+        .map(|a| {
+            if a == "--verbose" {
+                1
+            } else if a.len() > 1
+                && a.starts_with('-')
+                && !a.starts_with("--")
+                && a[1..].chars().all(|c| c.is_ascii_alphabetic())
+            {
+                a.matches('v').count()
+            } else {
+                0
+            }
+        })
+        .sum()
 }
 
-fn join_json_lines_array(lines: JsonValue) -> String {
-    Vec::<_>::try_from(lines)
-        .expect("could not convert string array to vec of json values")
-        .into_iter()
-        .map(|s| <JsonValue as TryInto<String>>::try_into(s).unwrap())
-        .collect::<Vec<String>>()
-        .join("")
+/// Write the diagnostics `report` if one was requested, and exit with the status for the run.
+fn finish(report: Option<&str>, diagnostics: &[Diagnostic], errors: Option<i32>) -> ! {
+    let code = match report {
+        Some(path) => {
+            diagnostics::write_report(path, diagnostics).unwrap_or_else(|e| fail(e));
+            diagnostics::exit_code(diagnostics, errors)
+        }
+        None => errors.unwrap_or(0),
+    };
+    std::process::exit(code);
 }
 
-fn format_cell(ctx: &Context, cell: &JsonValue) -> Result<String, J2TError> {
-    let hm: HashMap<_, _> = cell.clone().try_into()?;
-    let cell_type = String::try_from(hm["cell_type"].clone()).expect("string from cell_type");
-
-    if cell_type == "markdown" {
-        let joined: String =
-            <Vec<JsonValue> as TryFrom<JsonValue>>::try_from(hm["source"].clone())?
-                .into_iter()
-                .map(|s| <JsonValue as TryInto<String>>::try_into(s).unwrap())
-                .collect::<Vec<String>>()
-                .join("");
-        convert_markdown_to_typst(&joined)
-    } else if cell_type == "code" {
-        let exec_count = f64::try_from(hm["execution_count"].clone()).unwrap();
-        let joined_code: String =
-            <Vec<JsonValue> as TryFrom<JsonValue>>::try_from(hm["source"].clone())?
-                .into_iter()
-                .map(|s| <JsonValue as TryInto<String>>::try_into(s).unwrap())
-                .collect::<Vec<String>>()
-                .join("");
-        assert!(
-            !joined_code.contains('`'),
-            "Currently, code is not allowed to contain backticks!"
-        );
-        let result_joined = format_cell_result(ctx, &hm)?;
-        let code_content = format!(
-            r#"
-#move(align(right, box(text([[{}]], fill: blue), fill: red, inset: 0pt, height: 0pt)), dx: -25pt, dy: 10pt)
-#codeblock(lang: "{}", `{}`.text)
-#resultblock(`{}`.text)
-
-"#,
-            exec_count, ctx.lang, joined_code, result_joined
-        );
-
-        Ok(code_content)
-    } else {
-        Ok(String::new())
+/// How often `--watch` checks the input files for changes.
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Expand glob patterns in `paths` into the matching file names, for shells (or quoted arguments)
+/// that don't do it themselves.
+fn expand_globs(paths: &[String]) -> Result<Vec<String>, J2TError> {
+    let mut expanded = vec![];
+    for path in paths {
+        if is_remote(path) || !path.contains(['*', '?', '[']) {
+            expanded.push(path.clone());
+            continue;
+        }
+        let pattern_error = |e: String| J2TError {
+            msg: Some(format!("invalid pattern {}: {}", path, e)),
+            ..Default::default()
+        };
+        let mut matches = glob::glob(path)
+            .map_err(|e| pattern_error(e.to_string()))?
+            .map(|p| p.map(|p| p.to_string_lossy().into_owned()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| pattern_error(e.to_string()))?;
+        if matches.is_empty() {
+            return Err(pattern_error("no matching files".to_string()));
+        }
+        matches.sort();
+        expanded.append(&mut matches);
     }
+    Ok(expanded)
 }
 
-fn main() {
-    let (args, _rest) = opts! {
-        synopsis "Convert a jupyter notebook into typst source code.";
-        opt verbose:bool, desc:"Enable verbosity";
-        param infile:String, desc:"Input file name";
-        param outfile:String, desc:"Input file name";
-    }
-    .parse_or_exit();
-
-    let parsed_json = parse_notebook_file(&args.infile);
-    let parsed_dict = <HashMap<_, _>>::try_from(parsed_json.clone()).unwrap();
-
-    let metadata = HashMap::<_, _>::try_from(parsed_dict["metadata"].clone()).unwrap();
-    let kernelspec = HashMap::<_, _>::try_from(metadata["kernelspec"].clone()).unwrap();
-    let language: String = kernelspec["language"].clone().try_into().unwrap();
-
-    let ctx = Context {
-        verbose: args.verbose,
-        lang: language,
+/// Pair input files with output files. With `out_dir`, all `paths` are inputs and are converted
+/// into `<out_dir>/<name>.<extension>`; otherwise `paths` is an input file followed by an optional
+/// output file, which defaults to `<name>.<extension>` next to the input. `extension` gives the
+/// extension for an input file.
+fn plan_jobs(
+    paths: Vec<String>,
+    out_dir: Option<&str>,
+    extension: &dyn Fn(&str) -> &'static str,
+) -> Result<Vec<(String, String)>, J2TError> {
+    let usage_error = |msg: &str| J2TError {
+        msg: Some(msg.to_string()),
+        ..Default::default()
+    };
+    let typ_name = |infile: &str| {
+        let name = Path::new(infile).with_extension(extension(infile));
+        // Downloaded notebooks are converted into the working directory.
+        if is_remote(infile) {
+            PathBuf::from(name.file_name().unwrap_or_default())
+        } else {
+            name
+        }
     };
 
-    notebook_overview(&ctx, &parsed_json);
-
-    let cells =
-        <Vec<JsonValue> as TryFrom<JsonValue>>::try_from(parsed_dict["cells"].clone()).unwrap();
-
-    let mut outfile = fs::OpenOptions::new()
-        .write(true)
-        .truncate(true)
-        .create(true)
-        .open(args.outfile)
-        .expect("open output file");
-    use std::io::Write;
-    outfile.write(document_root.as_bytes());
-
-    let ixs = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, cells.len() - 1];
-
-    for i in ixs {
-        write!(
-            outfile,
-            "{}",
-            format_cell(&ctx, &cells[i]).expect("format failed")
-        );
+    if let Some(out_dir) = out_dir {
+        if paths.is_empty() {
+            return Err(usage_error("no input files given"));
+        }
+        fs::create_dir_all(out_dir)?;
+        return Ok(paths
+            .into_iter()
+            .map(|infile| {
+                let name = typ_name(&infile);
+                let outfile = Path::new(out_dir).join(name.file_name().unwrap_or_default());
+                (infile, outfile.to_string_lossy().into_owned())
+            })
+            .collect());
+    }
+    match paths.as_slice() {
+        [infile] if infile == STDIO_PATH => Ok(vec![(infile.clone(), STDIO_PATH.to_string())]),
+        [infile] => Ok(vec![(
+            infile.clone(),
+            typ_name(infile).to_string_lossy().into_owned(),
+        )]),
+        [infile, outfile] => Ok(vec![(infile.clone(), outfile.clone())]),
+        [] => Err(usage_error("no input file given")),
+        _ => Err(usage_error(
+            "converting several notebooks requires --out-dir",
+        )),
     }
 }
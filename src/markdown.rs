@@ -0,0 +1,373 @@
+//! Conversion of Markdown cells into Typst markup.
+
+use crate::assets;
+use crate::cell::{write_chapter_break, write_page_break};
+use crate::citations::write_with_citations;
+use crate::deflist::{split_definition_lists, Definition, Segment};
+use crate::document::{needs_call_terminator, typst_string};
+use crate::emoji::replace_emoji;
+use crate::latex::latex_to_typst;
+use crate::myst::{colon_fences_to_backticks, directive_to_typst, Directive};
+use crate::output::store_image;
+use crate::render::Context;
+use crate::typography::smart_typography;
+use crate::J2TError;
+
+use markdown::mdast::{AlignKind, List, Node, Table};
+
+use std::borrow::Cow;
+use std::fmt::Write;
+
+pub fn markdown_to_typst(ctx: &Context, n: &Node, out: &mut dyn Write) -> Result<(), J2TError> {
+    match n {
+        Node::Root(ref r) => write_blocks(ctx, &r.children, out)?,
+        Node::InlineCode(ref ic) => {
+            write!(out, "`{}`", ic.value)?;
+        }
+        Node::Heading(ref h) => {
+            // Typst only recognizes (and outlines) headings at the start of a line.
+            write!(
+                out,
+                "\n{} ",
+                std::iter::repeat("=")
+                    .take(h.depth as usize + ctx.heading_offset)
+                    .collect::<Vec<&str>>()
+                    .join("")
+            )?;
+            write_inlines(ctx, &h.children, out)?;
+            out.write_str("\n\n")?;
+        }
+        Node::Paragraph(ref p) => {
+            write_inlines(ctx, &p.children, out)?;
+            out.write_str("\n")?;
+        }
+        Node::Emphasis(ref e) => write_call(ctx, "emph", &e.children, out)?,
+        Node::Strong(ref s) => write_call(ctx, "strong", &s.children, out)?,
+        Node::Delete(ref d) => write_call(ctx, "strike", &d.children, out)?,
+        Node::Link(ref l) => {
+            write!(out, "#link({})", typst_string(&l.url))?;
+            // Autolinks show the URL already.
+            let autolink = matches!(l.children.as_slice(), [Node::Text(t)] if t.value == l.url);
+            if !autolink {
+                out.write_char('[')?;
+                write_inlines(ctx, &l.children, out)?;
+                out.write_char(']')?;
+            }
+        }
+        Node::Break(_) => out.write_str(" \\\n")?,
+        Node::ThematicBreak(_) => out.write_str("#line(length: 100%)\n")?,
+        Node::BlockQuote(ref q) => {
+            out.write_str("#quote(block: true)[\n")?;
+            write_blocks(ctx, &q.children, out)?;
+            out.write_str("]\n")?;
+        }
+        Node::List(ref l) => write_list(ctx, l, out)?,
+        Node::Table(ref t) => write_table(ctx, t, out)?,
+        // Link reference definitions; the references using them are reported.
+        Node::Definition(_) => {}
+        Node::Text(ref t) => {
+            let text = if ctx.opts.smartquotes {
+                smart_typography(&t.value)
+            } else {
+                Cow::Borrowed(t.value.as_str())
+            };
+            let text = replace_emoji(&text, ctx.opts.emoji_font.as_deref());
+            write_with_citations(out, &text)?;
+        }
+        Node::Image(ref img) if img.url.starts_with("attachment:") => {
+            let name = &img.url["attachment:".len()..];
+            let attachments = ctx.attachments.borrow();
+            let image = attachments.get(name).and_then(|a| {
+                a.mime_types()
+                    .into_iter()
+                    .find_map(|mime| Some((mime, assets::image_extension(mime)?, a.get(mime)?)))
+            });
+            match image {
+                Some((mime, extension, data)) => {
+                    let path = store_image(ctx, mime, extension, data).map_err(|e| J2TError {
+                        msg: Some(format!("attachment `{}`", name)),
+                        ..e
+                    })?;
+                    writeln!(out, "#image({})", typst_string(&path))?;
+                }
+                None => ctx.unsupported(
+                    None,
+                    format!("missing or unsupported attachment `{}`", name),
+                )?,
+            }
+        }
+        Node::InlineMath(ref m) => {
+            write!(out, "${}$", math_to_typst(ctx, &m.value)?)?;
+        }
+        Node::Math(ref m) => {
+            // Spaces inside the dollars make a block equation.
+            writeln!(out, "$ {} $", math_to_typst(ctx, &m.value)?)?;
+        }
+        Node::Code(ref c) if c.lang.as_deref().map_or(false, |l| l.starts_with('{')) => {
+            let lang = c.lang.as_deref().unwrap_or_default();
+            match Directive::from_code(lang, c.meta.as_deref(), &c.value) {
+                Some(directive) => directive_to_typst(ctx, &directive, out)?,
+                None => ctx.unsupported(None, format!("malformed directive {}", lang))?,
+            }
+        }
+        Node::Code(ref c) => {
+            write!(
+                out,
+                "```{}\n",
+                c.lang.as_ref().map(String::as_str).unwrap_or("")
+            )?;
+            out.write_str(c.value.as_str())?;
+            out.write_str("```\n")?;
+        }
+        _ => {
+            let name = unsupported_node_name(n);
+            // Reported once per cell, as a cell may contain many nodes of a kind.
+            if !ctx.unsupported_nodes.borrow().contains(&name) {
+                ctx.unsupported_nodes.borrow_mut().push(name);
+                ctx.unsupported(None, format!("dropped unsupported Markdown node {}", name))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The name of a Markdown node that isn't translated, for reporting it.
+fn unsupported_node_name(n: &Node) -> &'static str {
+    match n {
+        Node::Html(_) => "Html",
+        Node::Image(_) => "Image",
+        Node::ImageReference(_) => "ImageReference",
+        Node::LinkReference(_) => "LinkReference",
+        Node::FootnoteDefinition(_) => "FootnoteDefinition",
+        Node::FootnoteReference(_) => "FootnoteReference",
+        Node::Toml(_) | Node::Yaml(_) => "front matter",
+        _ => "of unknown kind",
+    }
+}
+
+/// Convert the inline nodes `children`. Calls like `#emph[..]` are ended with `;` where the
+/// following text would continue them.
+fn write_inlines(ctx: &Context, children: &[Node], out: &mut dyn Write) -> Result<(), J2TError> {
+    for (i, n) in children.iter().enumerate() {
+        markdown_to_typst(ctx, n, out)?;
+        let call = matches!(
+            n,
+            Node::Emphasis(_) | Node::Strong(_) | Node::Delete(_) | Node::Link(_)
+        );
+        if call {
+            if let Some(Node::Text(next)) = children.get(i + 1) {
+                if needs_call_terminator(&next.value) {
+                    out.write_char(';')?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Convert the block nodes `children`, separated by blank lines so that they don't run into
+/// each other.
+fn write_blocks(ctx: &Context, children: &[Node], out: &mut dyn Write) -> Result<(), J2TError> {
+    for (i, n) in children.iter().enumerate() {
+        if i > 0 {
+            out.write_char('\n')?;
+        }
+        markdown_to_typst(ctx, n, out)?;
+    }
+    Ok(())
+}
+
+/// Write `#function[children]`.
+fn write_call(
+    ctx: &Context,
+    function: &str,
+    children: &[Node],
+    out: &mut dyn Write,
+) -> Result<(), J2TError> {
+    write!(out, "#{}[", function)?;
+    write_inlines(ctx, children, out)?;
+    out.write_char(']')?;
+    Ok(())
+}
+
+/// Write a list as Typst list or numbered items, with the item bodies indented below them.
+fn write_list(ctx: &Context, list: &List, out: &mut dyn Write) -> Result<(), J2TError> {
+    for (i, item) in list.children.iter().enumerate() {
+        let marker = if list.ordered {
+            format!("{}.", list.start.unwrap_or(1) as usize + i)
+        } else {
+            "-".to_string()
+        };
+        let mut body = String::new();
+        match item {
+            Node::ListItem(ref item) => {
+                match item.checked {
+                    Some(true) => body.push_str("☑ "),
+                    Some(false) => body.push_str("☐ "),
+                    None => {}
+                }
+                write_blocks(ctx, &item.children, &mut body)?;
+            }
+            n => markdown_to_typst(ctx, n, &mut body)?,
+        }
+        if list.spread && i > 0 {
+            out.write_char('\n')?;
+        }
+        writeln!(out, "{} {}", marker, body.trim().replace('\n', "\n  "))?;
+    }
+    Ok(())
+}
+
+/// Write a GFM table as `#table`, with the first row as header.
+fn write_table(ctx: &Context, table: &Table, out: &mut dyn Write) -> Result<(), J2TError> {
+    let align = table
+        .align
+        .iter()
+        .map(|a| match a {
+            AlignKind::Left => "left",
+            AlignKind::Right => "right",
+            AlignKind::Center => "center",
+            AlignKind::None => "auto",
+        })
+        .collect::<Vec<_>>();
+    writeln!(
+        out,
+        "#table(\n  columns: {},\n  align: ({},),",
+        align.len(),
+        align.join(", ")
+    )?;
+    for (i, row) in table.children.iter().enumerate() {
+        let cells = match row {
+            Node::TableRow(ref row) => &row.children,
+            _ => continue,
+        };
+        let cells = cells
+            .iter()
+            .map(|cell| {
+                let mut content = String::new();
+                match cell {
+                    Node::TableCell(ref cell) => write_inlines(ctx, &cell.children, &mut content)?,
+                    n => markdown_to_typst(ctx, n, &mut content)?,
+                }
+                Ok(format!("[{}]", content.trim()))
+            })
+            .collect::<Result<Vec<_>, J2TError>>()?
+            .join(", ");
+        if i == 0 {
+            writeln!(out, "  table.header({}),", cells)?;
+        } else {
+            writeln!(out, "  {},", cells)?;
+        }
+    }
+    out.write_str(")\n")?;
+    Ok(())
+}
+
+pub fn convert_markdown_to_typst(ctx: &Context, s: &str) -> Result<String, J2TError> {
+    let mut out = String::new();
+    write_markdown(ctx, s, &mut out)?;
+    Ok(out)
+}
+
+/// Translate the LaTeX math `latex`, reporting unknown commands.
+fn math_to_typst(ctx: &Context, latex: &str) -> Result<String, J2TError> {
+    let (math, unknown) = latex_to_typst(latex, ctx.macros);
+    for command in unknown {
+        ctx.unsupported(None, format!("unknown LaTeX command \\{}", command))?;
+    }
+    Ok(math.trim().to_string())
+}
+
+/// Convert the Markdown `s` and write the Typst markup into `out`.
+pub fn write_markdown(ctx: &Context, s: &str, out: &mut dyn Write) -> Result<(), J2TError> {
+    write_source(ctx, s, false, out)
+}
+
+/// Like `write_markdown`, for the source of a Markdown cell. Page breaks requested by
+/// `break_before_headings` and chapters for `split_by_heading` are only possible at its top
+/// level, not nested in outputs or directives.
+pub fn write_markdown_cell(ctx: &Context, s: &str, out: &mut dyn Write) -> Result<(), J2TError> {
+    write_source(ctx, s, true, out)
+}
+
+fn write_source(ctx: &Context, s: &str, cell: bool, out: &mut dyn Write) -> Result<(), J2TError> {
+    let source = colon_fences_to_backticks(s);
+    for segment in split_definition_lists(&source) {
+        let markdown = match segment {
+            Segment::Markdown(markdown) => markdown,
+            Segment::Definitions(items) => {
+                write_definitions(ctx, &items, out)?;
+                continue;
+            }
+        };
+        let ast = parse_markdown(&markdown)?;
+        let children = match ast {
+            Node::Root(ref r) if cell => &r.children,
+            _ => {
+                markdown_to_typst(ctx, &ast, out)?;
+                continue;
+            }
+        };
+        for (i, n) in children.iter().enumerate() {
+            if i > 0 {
+                out.write_char('\n')?;
+            }
+            if let Node::Heading(ref h) = n {
+                if ctx
+                    .opts
+                    .split_by_heading
+                    .map_or(false, |l| h.depth as usize <= l)
+                {
+                    write_chapter_break(ctx, out)?;
+                }
+                if ctx.opts.break_before_headings.contains(&(h.depth as usize)) {
+                    write_page_break(ctx, out)?;
+                }
+            }
+            markdown_to_typst(ctx, n, out)?;
+        }
+    }
+    Ok(())
+}
+
+/// Write a definition list as `#terms`, with the definitions of a term in separate paragraphs.
+fn write_definitions(
+    ctx: &Context,
+    items: &[Definition],
+    out: &mut dyn Write,
+) -> Result<(), J2TError> {
+    out.write_str("#terms(\n")?;
+    for item in items {
+        let definitions = item
+            .definitions
+            .iter()
+            .map(|d| Ok(convert_markdown_to_typst(ctx, d)?.trim().to_string()))
+            .collect::<Result<Vec<_>, J2TError>>()?;
+        writeln!(
+            out,
+            "  terms.item[{}][{}],",
+            convert_markdown_to_typst(ctx, item.term)?.trim(),
+            definitions.join("\n\n")
+        )?;
+    }
+    out.write_str(")\n")?;
+    Ok(())
+}
+
+fn parse_markdown(s: &str) -> Result<Node, J2TError> {
+    let po = markdown::ParseOptions {
+        // Jupyter renders GitHub-flavored Markdown. Footnotes are left out, as their
+        // definitions can't be written where they are referenced.
+        constructs: markdown::Constructs {
+            math_flow: true,
+            math_text: true,
+            gfm_footnote_definition: false,
+            gfm_label_start_footnote: false,
+            ..markdown::Constructs::gfm()
+        },
+        ..Default::default()
+    };
+    let ast = markdown::to_mdast(s, &po)?;
+    trace!("{:?}", ast);
+    Ok(ast)
+}
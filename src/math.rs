@@ -0,0 +1,334 @@
+//! User-defined LaTeX macros, and the tokenizer shared with the translation of math in
+//! `latex`.
+//!
+//! Macros are collected from the JSON file `math_macros`, from
+//! `metadata.latex_macros` (both mapping names to a definition, or to a definition and the number
+//! of parameters, as in MathJax) and from `\newcommand`, `\renewcommand`, `\def` and
+//! `\DeclareMathOperator` in Markdown cells. They are defined as functions named `tex-<name>`
+//! before the first cell, and math using them calls these functions.
+
+use crate::config::Options;
+use crate::latex::translate_tokens;
+use crate::notebook::{CellType, Notebook};
+use crate::J2TError;
+
+use tinyjson::JsonValue;
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::fs;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Token<'a> {
+    /// A command like `\frac`, without backslash. Also a single escaped character like `\{`.
+    Command(&'a str),
+    Char(char),
+    Open,
+    Close,
+}
+
+pub fn tokenize(s: &str) -> Vec<Token<'_>> {
+    let mut tokens = vec![];
+    let mut chars = s.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => {
+                let start = i + 1;
+                let mut end = start;
+                while let Some(&(j, c)) = chars.peek() {
+                    if !c.is_ascii_alphabetic() {
+                        break;
+                    }
+                    end = j + 1;
+                    chars.next();
+                }
+                if end == start {
+                    if let Some((j, c)) = chars.next() {
+                        end = j + c.len_utf8();
+                    }
+                } else if chars.peek().map(|&(_, c)| c) == Some('*') {
+                    // Starred variants like `\operatorname*` are treated like the plain ones.
+                    chars.next();
+                }
+                tokens.push(Token::Command(&s[start..end]));
+            }
+            '{' => tokens.push(Token::Open),
+            '}' => tokens.push(Token::Close),
+            // A comment runs to the end of the line.
+            '%' => while chars.next_if(|&(_, c)| c != '\n').is_some() {},
+            c => tokens.push(Token::Char(c)),
+        }
+    }
+    tokens
+}
+
+/// Write `tokens` back as LaTeX.
+fn untokenize(tokens: &[Token]) -> String {
+    let mut out = String::new();
+    for token in tokens {
+        match token {
+            Token::Command(name) => write!(out, "\\{} ", name).unwrap(),
+            Token::Char(c) => out.push(*c),
+            Token::Open => out.push('{'),
+            Token::Close => out.push('}'),
+        }
+    }
+    out
+}
+
+/// A user-defined macro.
+#[derive(Debug, Clone)]
+struct Macro {
+    params: usize,
+    /// The LaTeX definition, with parameters `#1`, `#2`, ...
+    body: String,
+}
+
+/// The macros defined for a notebook, by name without backslash.
+#[derive(Debug, Default)]
+pub struct Macros {
+    macros: HashMap<String, Macro>,
+    /// Names in order of definition.
+    order: Vec<String>,
+}
+
+impl Macros {
+    /// Collect the macros for `nb`: from the file `opts.math_macros`, the notebook metadata and
+    /// definitions in its Markdown cells, with later definitions replacing earlier ones.
+    pub fn collect(opts: &Options, nb: &Notebook) -> Result<Macros, J2TError> {
+        let mut macros = Macros::default();
+        if let Some(ref path) = opts.math_macros {
+            let json = fs::read_to_string(path)
+                .map_err(J2TError::from)
+                .and_then(|s| Ok(s.parse::<JsonValue>()?))
+                .map_err(|e| J2TError {
+                    msg: Some(format!("math macros {}", path)),
+                    ..e
+                })?;
+            macros.insert_json(json, path)?;
+        }
+        if let Some(json) = nb.metadata.get("latex_macros") {
+            macros.insert_json(json.clone(), "metadata.latex_macros")?;
+        }
+        for cell in nb.cells.iter().flatten() {
+            if cell.cell_type == CellType::Markdown && cell.source.contains('\\') {
+                macros.scan(&cell.source);
+            }
+        }
+        Ok(macros)
+    }
+
+    /// The number of parameters of the macro `name`, if it is defined.
+    pub fn params(&self, name: &str) -> Option<usize> {
+        self.macros.get(name).map(|m| m.params)
+    }
+
+    fn insert(&mut self, name: &str, params: usize, body: String) {
+        let name = name.trim_start_matches('\\');
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphabetic()) {
+            warn!("ignoring LaTeX macro with invalid name `{}`", name);
+            return;
+        }
+        if !self.macros.contains_key(name) {
+            self.order.push(name.to_string());
+        }
+        self.macros.insert(name.to_string(), Macro { params, body });
+    }
+
+    /// Add the macros in `json`, an object mapping names to a definition, or to an array of
+    /// definition and number of parameters.
+    fn insert_json(&mut self, json: JsonValue, source: &str) -> Result<(), J2TError> {
+        let invalid = |msg: String| J2TError {
+            msg: Some(format!("{}: {}", source, msg)),
+            ..Default::default()
+        };
+        let macros = match json {
+            JsonValue::Object(macros) => macros,
+            _ => return Err(invalid("expected an object".to_string())),
+        };
+        let mut macros = macros.into_iter().collect::<Vec<_>>();
+        // Objects are unordered; definitions are ordered by dependency when written anyway.
+        macros.sort_by(|(a, _), (b, _)| a.cmp(b));
+        for (name, definition) in macros {
+            let (body, params) = match definition {
+                JsonValue::String(body) => (body, 0.0),
+                JsonValue::Array(a) => match a.as_slice() {
+                    [JsonValue::String(body), JsonValue::Number(params), ..] => {
+                        (body.clone(), *params)
+                    }
+                    [JsonValue::String(body)] => (body.clone(), 0.0),
+                    _ => return Err(invalid(format!("invalid definition of `{}`", name))),
+                },
+                _ => return Err(invalid(format!("invalid definition of `{}`", name))),
+            };
+            self.insert(&name, params as usize, body);
+        }
+        Ok(())
+    }
+
+    /// Add the macros defined in the LaTeX (or Markdown) `source`.
+    fn scan(&mut self, source: &str) {
+        let tokens = tokenize(source);
+        let mut pos = 0;
+        while pos < tokens.len() {
+            match parse_definition(&tokens, &mut pos) {
+                Some((name, params, body)) => self.insert(name, params, body),
+                None => pos += 1,
+            }
+        }
+    }
+
+    /// The Typst function definitions for all macros, each after the macros it uses.
+    pub fn to_typst(&self) -> String {
+        let mut out = String::new();
+        let mut done = vec![];
+        for name in self.order.iter() {
+            self.write_definition(name, &mut done, &mut out);
+        }
+        out
+    }
+
+    fn write_definition<'a>(&'a self, name: &'a str, done: &mut Vec<&'a str>, out: &mut String) {
+        if done.contains(&name) {
+            return;
+        }
+        done.push(name);
+        let m = &self.macros[name];
+        let tokens = tokenize(&m.body);
+        for token in tokens.iter() {
+            if let Token::Command(used) = token {
+                if let Some((used, _)) = self.macros.get_key_value(*used) {
+                    self.write_definition(used, done, out);
+                }
+            }
+        }
+        let (body, unknown) = translate_tokens(&tokens, self);
+        for command in unknown {
+            warn!("macro \\{}: unknown LaTeX command \\{}", name, command);
+        }
+        let params = (1..=m.params)
+            .map(|i| format!("a{}", i))
+            .collect::<Vec<_>>()
+            .join(", ");
+        writeln!(out, "#let tex-{}({}) = ${}$", name, params, body.trim()).unwrap();
+    }
+}
+
+/// If a macro definition starts at `tokens[*pos]`, parse it and move `pos` past it. Returns the
+/// name, the number of parameters and the body.
+pub fn parse_definition<'a>(
+    tokens: &[Token<'a>],
+    pos: &mut usize,
+) -> Option<(&'a str, usize, String)> {
+    let mut i = *pos;
+    let command = match tokens.get(i)? {
+        Token::Command(command) => *command,
+        _ => return None,
+    };
+    i += 1;
+    let (name, params) = match command {
+        "newcommand" | "renewcommand" | "providecommand" | "DeclareMathOperator" => {
+            let name = match tokens.get(i..i + 3)? {
+                [Token::Open, Token::Command(name), Token::Close] => {
+                    i += 3;
+                    *name
+                }
+                [Token::Command(name), ..] => {
+                    i += 1;
+                    *name
+                }
+                _ => return None,
+            };
+            let mut params = 0;
+            // `[2]` parameters, and a default for the first one, which is ignored.
+            while tokens.get(i) == Some(&Token::Char('[')) {
+                let len = tokens[i..].iter().position(|t| *t == Token::Char(']'))?;
+                if params == 0 {
+                    let digits = tokens[i + 1..i + len]
+                        .iter()
+                        .map(|t| match t {
+                            Token::Char(c) => *c,
+                            _ => ' ',
+                        })
+                        .collect::<String>();
+                    params = digits.trim().parse().unwrap_or(0);
+                }
+                i += len + 1;
+            }
+            (name, params)
+        }
+        "def" => {
+            let name = match tokens.get(i)? {
+                Token::Command(name) => *name,
+                _ => return None,
+            };
+            i += 1;
+            let mut params = 0;
+            while let (Some(Token::Char('#')), Some(Token::Char(c))) =
+                (tokens.get(i), tokens.get(i + 1))
+            {
+                params = c.to_digit(10)? as usize;
+                i += 2;
+            }
+            (name, params)
+        }
+        _ => return None,
+    };
+    if tokens.get(i) != Some(&Token::Open) {
+        return None;
+    }
+    let len = group_len(&tokens[i..])?;
+    let body = untokenize(&tokens[i + 1..i + len - 1]);
+    *pos = i + len;
+    if command == "DeclareMathOperator" {
+        return Some((name, 0, format!("\\operatorname{{{}}}", body)));
+    }
+    Some((name, params, body))
+}
+
+/// The number of tokens of the group starting with `Open` at `tokens[0]`, including the braces.
+pub fn group_len(tokens: &[Token]) -> Option<usize> {
+    let mut depth = 0;
+    for (i, token) in tokens.iter().enumerate() {
+        match token {
+            Token::Open => depth += 1,
+            Token::Close => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i + 1);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::latex::latex_to_typst;
+
+    #[test]
+    fn definitions() {
+        let mut macros = Macros::default();
+        macros.scan(
+            r"\newcommand{\R}{\mathbb{R}} \def\norm#1{\| #1 \|} \DeclareMathOperator{\tr}{tr}",
+        );
+        assert_eq!(macros.params("R"), Some(0));
+        assert_eq!(macros.params("norm"), Some(1));
+        assert_eq!(
+            macros.to_typst(),
+            "#let tex-R() = $bb(R)$\n#let tex-norm(a1) = $||  #(a1) ||$\n#let tex-tr() = $op(\"tr\")$\n"
+        );
+    }
+
+    #[test]
+    fn expansion() {
+        let mut macros = Macros::default();
+        macros.scan(r"\newcommand{\R}{\mathbb{R}} \newcommand{\pair}[2]{(#1, #2)}");
+        let (math, unknown) = latex_to_typst(r"x \in \R^2, \pair{a,b}{c}", &macros);
+        assert!(unknown.is_empty());
+        assert_eq!(math, r"x in #tex-R()^2, #tex-pair($a\,b$, $c$)");
+    }
+}
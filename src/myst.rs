@@ -0,0 +1,235 @@
+//! MyST Markdown directives, as used in Jupyter Book notebooks.
+//!
+//! Directives are written as code blocks whose language is the directive name in braces, or as
+//! `:::` colon fences:
+//!
+//! ```text
+//! :::{figure} images/setup.png
+//! :width: 60%
+//!
+//! The experimental setup.
+//! :::
+//! ```
+//!
+//! Admonitions (`note`, `warning`, ...) become `admonition` callout boxes and `figure` becomes a
+//! captioned `#figure`. Other directives are shown as code blocks.
+
+use crate::document::{typst_length, typst_string};
+use crate::markdown::convert_markdown_to_typst;
+use crate::render::Context;
+use crate::typography::smart_typography;
+use crate::J2TError;
+
+use std::fmt::Write;
+
+/// Admonition directives, rendered by the `admonition` template function.
+const ADMONITIONS: &[&str] = &[
+    "note",
+    "tip",
+    "hint",
+    "important",
+    "seealso",
+    "warning",
+    "caution",
+    "attention",
+    "danger",
+    "error",
+];
+
+/// A directive: its name, argument, `:key: value` options and body.
+pub struct Directive<'a> {
+    pub name: &'a str,
+    pub argument: &'a str,
+    pub options: Vec<(&'a str, &'a str)>,
+    pub body: String,
+}
+
+impl<'a> Directive<'a> {
+    /// The directive in a code block with language `lang`, like `{note}`, if it is one.
+    pub fn from_code(
+        lang: &'a str,
+        meta: Option<&'a str>,
+        value: &'a str,
+    ) -> Option<Directive<'a>> {
+        let name = lang.strip_prefix('{')?.strip_suffix('}')?;
+        let mut lines = value.lines().peekable();
+        let mut options = vec![];
+        while let Some(option) = lines.peek().and_then(|l| parse_option(l)) {
+            options.push(option);
+            lines.next();
+        }
+        Some(Directive {
+            name,
+            argument: meta.unwrap_or_default().trim(),
+            options,
+            body: lines.collect::<Vec<_>>().join("\n"),
+        })
+    }
+
+    fn option(&self, key: &str) -> Option<&'a str> {
+        self.options
+            .iter()
+            .find(|(k, _)| *k == key)
+            .map(|(_, v)| *v)
+    }
+}
+
+/// Parse an option line `:key: value`.
+fn parse_option(line: &str) -> Option<(&str, &str)> {
+    let (key, value) = line.strip_prefix(':')?.split_once(':')?;
+    if key.is_empty() || key.contains(char::is_whitespace) {
+        return None;
+    }
+    Some((key, value.trim()))
+}
+
+/// Write the directive `d` as Typst.
+pub fn directive_to_typst(
+    ctx: &Context,
+    d: &Directive,
+    out: &mut dyn Write,
+) -> Result<(), J2TError> {
+    if d.name == "admonition" || ADMONITIONS.contains(&d.name) {
+        // The generic `admonition` takes its title as argument and its kind from `:class:`.
+        let kind = match d.name {
+            "admonition" => d.option("class").unwrap_or("note"),
+            name => name,
+        };
+        let title = if d.argument.is_empty() {
+            "none".to_string()
+        } else if ctx.opts.smartquotes {
+            typst_string(&smart_typography(d.argument))
+        } else {
+            typst_string(d.argument)
+        };
+        writeln!(
+            out,
+            "#admonition(kind: {}, title: {})[{}]",
+            typst_string(kind),
+            title,
+            convert_markdown_to_typst(ctx, &d.body)?
+        )?;
+    } else if d.name == "figure" {
+        let mut image = typst_string(d.argument);
+        if let Some(width) = d.option("width").and_then(figure_width) {
+            write!(image, ", width: {}", width)?;
+        }
+        write!(
+            out,
+            "#figure(image({}), caption: [{}])",
+            image,
+            convert_markdown_to_typst(ctx, &d.body)?.trim()
+        )?;
+        match d.option("name") {
+            Some(name) => writeln!(out, " <{}>", name)?,
+            None => writeln!(out)?,
+        }
+    } else {
+        ctx.unsupported(None, format!("unsupported directive {{{}}}", d.name))?;
+        let fence = backtick_fence(&d.body);
+        writeln!(out, "{}\n{}\n{}", fence, d.body, fence)?;
+    }
+    Ok(())
+}
+
+/// Typst width for a figure width like `60%` or `8cm`. Pixel widths have no Typst equivalent.
+fn figure_width(width: &str) -> Option<String> {
+    match width.strip_suffix('%') {
+        Some(percent) => percent.parse::<f64>().ok().map(|_| width.to_string()),
+        None => typst_length(width).ok(),
+    }
+}
+
+/// A backtick fence longer than any backtick run in `text`, so that `text` can't close it.
+fn backtick_fence(text: &str) -> String {
+    let longest = text.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    "`".repeat(longest.max(2) + 1)
+}
+
+/// Rewrite `:::{name}` colon fences into backtick fences, which the Markdown parser turns into
+/// code blocks recognized as directives. A fence is closed by a line with as many colons, so
+/// nested directives use fewer colons than the enclosing one.
+pub fn colon_fences_to_backticks(source: &str) -> String {
+    let lines = source.lines().collect::<Vec<_>>();
+    let mut out = String::with_capacity(source.len());
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        let colons = line.len() - line.trim_start_matches(':').len();
+        let close = if colons >= 3 && line[colons..].starts_with('{') {
+            lines[i + 1..]
+                .iter()
+                .position(|l| l.trim_end() == &line[..colons])
+                .map(|p| i + 1 + p)
+        } else {
+            None
+        };
+        match close {
+            Some(close) => {
+                let body = &lines[i + 1..close];
+                let fence = backtick_fence(&body.join("\n"));
+                writeln!(out, "{}{}", fence, &line[colons..]).unwrap();
+                for body_line in body {
+                    writeln!(out, "{}", body_line).unwrap();
+                }
+                writeln!(out, "{}", fence).unwrap();
+                i = close + 1;
+            }
+            _ => {
+                writeln!(out, "{}", line).unwrap();
+                i += 1;
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn colon_fences() {
+        assert_eq!(
+            colon_fences_to_backticks(":::{note}\nText\n:::\nAfter\n"),
+            "```{note}\nText\n```\nAfter\n"
+        );
+        // The inner directive is converted along with the body of the outer one.
+        assert_eq!(
+            colon_fences_to_backticks("::::{important}\n:::{note}\nInner\n:::\n::::\n"),
+            "```{important}\n:::{note}\nInner\n:::\n```\n"
+        );
+        // Longer than the backtick runs in the body.
+        assert_eq!(
+            colon_fences_to_backticks(":::{note}\n````python\nx = `1`\n````\n:::\n"),
+            "`````{note}\n````python\nx = `1`\n````\n`````\n"
+        );
+        // Unclosed fences and plain colons are left alone.
+        let source = ":::{note}\nNever closed\n::: not a fence\n";
+        assert_eq!(colon_fences_to_backticks(source), source);
+    }
+
+    #[test]
+    fn fences() {
+        assert_eq!(backtick_fence("plain"), "```");
+        assert_eq!(backtick_fence("a ``` b ````` c"), "``````");
+    }
+
+    #[test]
+    fn options() {
+        let d = Directive::from_code(
+            "{figure}",
+            Some(" images/setup.png "),
+            ":width: 60%\n:name: fig-setup\n:not an option\nThe setup.\n:width: ignored",
+        )
+        .unwrap();
+        assert_eq!(d.name, "figure");
+        assert_eq!(d.argument, "images/setup.png");
+        assert_eq!(d.options, [("width", "60%"), ("name", "fig-setup")]);
+        assert_eq!(d.option("width"), Some("60%"));
+        assert_eq!(d.body, ":not an option\nThe setup.\n:width: ignored");
+        assert!(Directive::from_code("python", None, "x = 1").is_none());
+        assert_eq!(figure_width("8cm").as_deref(), Some("8cm"));
+        assert_eq!(figure_width("300px"), None);
+    }
+}
@@ -0,0 +1,509 @@
+//! The notebook data model, read from nbformat JSON.
+
+use crate::config::Options;
+use crate::{jupytext, quarto, upgrade};
+use crate::{J2TError, J2TErrorKind, STDIO_PATH};
+
+use tinyjson::JsonValue;
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read};
+use std::net::IpAddr;
+use std::path::Path;
+
+pub fn notebook_overview(nb: &Notebook) {
+    debug!("Notebook with {} cells", nb.cells.len());
+    debug!("Version: {}.{}", nb.nbformat, nb.nbformat_minor);
+    debug!(
+        "Language: {}",
+        kernel_language(&nb.metadata)
+            .as_deref()
+            .unwrap_or("unknown")
+    );
+}
+
+/// Highlighting language used if the notebook doesn't tell its kernel's language.
+pub const PLAIN_TEXT_LANG: &str = "txt";
+
+/// The programming language of the notebook's kernel: `metadata.kernelspec.language`, or
+/// `metadata.language_info.name` for kernels that don't set it.
+pub fn kernel_language(metadata: &HashMap<String, JsonValue>) -> Option<String> {
+    [("kernelspec", "language"), ("language_info", "name")]
+        .iter()
+        .find_map(|(object, key)| {
+            metadata
+                .get(*object)?
+                .get::<HashMap<String, JsonValue>>()?
+                .get(*key)?
+                .get::<String>()
+                .filter(|l| !l.is_empty())
+                .cloned()
+        })
+}
+
+/// Whether `path` names a notebook, or a script read as one, judging by its extension.
+pub fn is_notebook_path<S: AsRef<Path>>(path: S) -> bool {
+    path.as_ref()
+        .extension()
+        .map_or(false, |e| e == "ipynb" || e == "qmd")
+        || jupytext::script_language(path.as_ref()).is_some()
+}
+
+/// Whether `infile` is the URL of a notebook to download rather than a file name.
+pub fn is_remote(infile: &str) -> bool {
+    [
+        "http://",
+        "https://",
+        "jupyter://",
+        "jupyter+http://",
+        "jupyter+https://",
+    ]
+    .iter()
+    .any(|scheme| infile.starts_with(scheme))
+}
+
+/// Whether `host`, a host name or IP address with an optional port like in a URL or `Host`
+/// header, names the loopback interface.
+pub fn is_loopback_host(host: &str) -> bool {
+    let name = match host.strip_prefix('[') {
+        // An IPv6 address, like `[::1]:8700`.
+        Some(rest) => rest.split(']').next().unwrap_or_default(),
+        None => host.split(':').next().unwrap_or_default(),
+    };
+    name.eq_ignore_ascii_case("localhost")
+        || name.parse::<IpAddr>().map_or(false, |ip| ip.is_loopback())
+}
+
+/// Read the notebook `infile`, which is a file name or a URL. `token` authenticates requests to
+/// a Jupyter Server.
+pub fn read_notebook(infile: &str, token: Option<&str>) -> Result<JsonValue, J2TError> {
+    if is_remote(infile) {
+        fetch_notebook(infile, token)
+    } else {
+        parse_notebook_file(infile)
+    }
+}
+
+#[cfg(feature = "remote")]
+fn fetch_notebook(url: &str, token: Option<&str>) -> Result<JsonValue, J2TError> {
+    crate::remote::fetch(url, token)
+}
+
+#[cfg(not(feature = "remote"))]
+fn fetch_notebook(url: &str, _token: Option<&str>) -> Result<JsonValue, J2TError> {
+    Err(J2TError {
+        msg: Some(format!(
+            "{}: downloading notebooks requires jupyter2typst to be built with the `remote` feature",
+            url
+        )),
+        ..Default::default()
+    })
+}
+
+pub fn parse_notebook_file<S: AsRef<Path>>(filename: S) -> Result<JsonValue, J2TError> {
+    let file = if filename.as_ref() == Path::new(STDIO_PATH) {
+        let mut buf = vec![];
+        io::stdin().read_to_end(&mut buf)?;
+        buf
+    } else {
+        fs::read(filename.as_ref())?
+    };
+    let source =
+        String::from_utf8(file).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    parse_notebook_source(&source, filename.as_ref())
+}
+
+/// Parse `source`, the contents of the file `path`: a notebook, or a document read as one.
+pub fn parse_notebook_source(source: &str, path: &Path) -> Result<JsonValue, J2TError> {
+    if path.extension().map_or(false, |e| e == "qmd") {
+        return quarto::parse(source, path);
+    }
+    match jupytext::script_language(path) {
+        Some(language) => jupytext::parse(source, language),
+        None => parse_notebook(source),
+    }
+}
+
+/// Parse the notebook JSON `source`, upgrading legacy notebooks to nbformat 4.
+pub fn parse_notebook(source: &str) -> Result<JsonValue, J2TError> {
+    Ok(upgrade::upgrade(source.parse()?))
+}
+
+/// Join a multi-line string, which notebooks store either as a single string or as a list of
+/// lines.
+pub fn join_json_lines_array(lines: JsonValue) -> Result<String, J2TError> {
+    if let JsonValue::String(s) = lines {
+        return Ok(s);
+    }
+    Ok(Vec::<_>::try_from(lines)?
+        .into_iter()
+        .map(<JsonValue as TryInto<String>>::try_into)
+        .collect::<Result<Vec<String>, _>>()?
+        .join(""))
+}
+
+/// Convert `value`, found at `path` in the notebook, into a JSON object.
+pub fn json_object(value: JsonValue, path: &str) -> Result<HashMap<String, JsonValue>, J2TError> {
+    HashMap::try_from(value).map_err(|e| J2TError {
+        kind: J2TErrorKind::Json(e),
+        msg: Some(format!("`{}` is not an object", path)),
+        ..Default::default()
+    })
+}
+
+fn json_path(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+/// Take `key` out of the JSON object `obj`, found at `path` in the notebook. Values are moved
+/// rather than copied, as outputs may hold megabytes of image data.
+pub fn json_take(
+    obj: &mut HashMap<String, JsonValue>,
+    path: &str,
+    key: &str,
+) -> Result<JsonValue, J2TError> {
+    obj.remove(key).ok_or_else(|| J2TError {
+        msg: Some(format!("missing key `{}`", json_path(path, key))),
+        ..Default::default()
+    })
+}
+
+/// Like `json_take`, but also convert the value into `T`.
+pub fn json_get<T>(
+    obj: &mut HashMap<String, JsonValue>,
+    path: &str,
+    key: &str,
+) -> Result<T, J2TError>
+where
+    T: TryFrom<JsonValue, Error = tinyjson::UnexpectedValue>,
+{
+    T::try_from(json_take(obj, path, key)?).map_err(|e| J2TError {
+        kind: J2TErrorKind::Json(e),
+        msg: Some(format!("unexpected type of `{}`", json_path(path, key))),
+        ..Default::default()
+    })
+}
+
+/// Like `json_take`, but for multi-line strings.
+pub fn json_lines(
+    obj: &mut HashMap<String, JsonValue>,
+    path: &str,
+    key: &str,
+) -> Result<String, J2TError> {
+    join_json_lines_array(json_take(obj, path, key)?).map_err(|e| J2TError {
+        msg: Some(format!(
+            "`{}` is not a string or list of strings",
+            json_path(path, key)
+        )),
+        ..e
+    })
+}
+
+/// The highlighting language of code cells, by `--lang` or the kernel language.
+pub fn notebook_language(
+    opts: &Options,
+    metadata: &HashMap<String, JsonValue>,
+    infile: &str,
+) -> String {
+    match opts.lang.clone().or_else(|| kernel_language(metadata)) {
+        Some(lang) => raw_language(opts, &lang),
+        None => {
+            debug!("{}: unknown kernel language, not highlighting code", infile);
+            PLAIN_TEXT_LANG.to_string()
+        }
+    }
+}
+
+/// Kernel languages whose Typst `raw` language differs, after removing version numbers.
+const RAW_LANGUAGES: &[(&str, &str)] = &[
+    ("ipython", "python"),
+    ("ir", "r"),
+    ("sh", "bash"),
+    ("zsh", "bash"),
+    ("shell", "bash"),
+    ("c++", "cpp"),
+    ("xcpp", "cpp"),
+    ("c#", "cs"),
+    ("csharp", "cs"),
+    (".net-csharp", "cs"),
+    ("f#", "fs"),
+    ("fsharp", "fs"),
+    (".net-fsharp", "fs"),
+    ("javascript", "js"),
+    ("node", "js"),
+    ("nodejs", "js"),
+    ("typescript", "ts"),
+    ("octave", "matlab"),
+    ("wolfram language", "mathematica"),
+];
+
+/// The Typst `raw` language for the kernel language `lang`: from `opts.lang_map`, or the
+/// built-in table for names like `python3`, `ir` or `julia-1.10`.
+pub fn raw_language(opts: &Options, lang: &str) -> String {
+    let lang = lang.trim().to_lowercase();
+    if let Some(mapped) = opts.lang_map.get(&lang) {
+        return mapped.clone();
+    }
+    // `python3`, `julia-1.10`, `xcpp17`
+    let base = lang
+        .trim_end_matches(|c: char| c.is_ascii_digit() || c == '.')
+        .trim_end_matches(['-', ' ']);
+    let base = if base.is_empty() { &lang } else { base };
+    if let Some(mapped) = opts.lang_map.get(base) {
+        return mapped.clone();
+    }
+    RAW_LANGUAGES
+        .iter()
+        .find(|(kernel, _)| *kernel == base)
+        .map_or(base, |(_, raw)| raw)
+        .to_string()
+}
+
+/// The name of `infile` shown in the document.
+pub fn notebook_name(infile: &str) -> String {
+    if infile == STDIO_PATH {
+        "<stdin>".to_string()
+    } else {
+        Path::new(infile)
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+}
+
+/// A notebook in nbformat 4. Older notebooks are upgraded by `parse_notebook`.
+pub struct Notebook {
+    pub nbformat: u32,
+    pub nbformat_minor: u32,
+    pub metadata: HashMap<String, JsonValue>,
+    /// The cells in order. Cells that could not be read are kept as errors, so that they can be
+    /// skipped without shifting the indices of the others.
+    pub cells: Vec<Result<Cell, J2TError>>,
+}
+
+impl Notebook {
+    pub fn from_json(json: JsonValue) -> Result<Notebook, J2TError> {
+        let mut nb = json_object(json, "notebook")?;
+        let nbformat = json_get::<f64>(&mut nb, "", "nbformat")?;
+        if nbformat != 4.0 {
+            return Err(J2TError {
+                msg: Some(format!("unsupported nbformat version {}", nbformat)),
+                ..Default::default()
+            });
+        }
+        let cells: Vec<JsonValue> = json_get(&mut nb, "", "cells")?;
+        Ok(Notebook {
+            nbformat: nbformat as u32,
+            nbformat_minor: nb
+                .get("nbformat_minor")
+                .and_then(|v| v.get::<f64>())
+                .map_or(0, |v| *v as u32),
+            metadata: json_get(&mut nb, "", "metadata")?,
+            cells: cells
+                .into_iter()
+                .enumerate()
+                .map(|(i, cell)| Cell::from_json(cell).map_err(|e| J2TError { cell: Some(i), ..e }))
+                .collect(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CellType {
+    Markdown,
+    Code,
+    Raw,
+}
+
+pub struct Cell {
+    pub cell_type: CellType,
+    pub source: String,
+    pub metadata: HashMap<String, JsonValue>,
+    /// Files attached to a Markdown cell, by name.
+    pub attachments: HashMap<String, MimeBundle>,
+    /// `None` for code cells that were never executed.
+    pub execution_count: Option<u64>,
+    /// Outputs of a code cell. Outputs that could not be read are kept as errors.
+    pub outputs: Vec<Result<Output, J2TError>>,
+    /// Hash of the cell's JSON, identifying it in the cache. Only computed if caching is on.
+    pub fingerprint: Option<u64>,
+}
+
+impl Cell {
+    pub fn from_json(json: JsonValue) -> Result<Cell, J2TError> {
+        let mut cell = json_object(json, "cell")?;
+        let cell_type = match json_get::<String>(&mut cell, "", "cell_type")?.as_str() {
+            "markdown" => CellType::Markdown,
+            "code" => CellType::Code,
+            "raw" => CellType::Raw,
+            other => {
+                return Err(J2TError {
+                    msg: Some(format!("unknown cell type `{}`", other)),
+                    ..Default::default()
+                })
+            }
+        };
+        let attachments = match cell.remove("attachments") {
+            Some(attachments) => json_object(attachments, "attachments")?
+                .into_iter()
+                .map(|(name, bundle)| {
+                    let bundle = MimeBundle::from_json(bundle, &format!("attachments.{}", name))?;
+                    Ok((name, bundle))
+                })
+                .collect::<Result<_, J2TError>>()?,
+            None => HashMap::new(),
+        };
+        let outputs = if cell_type == CellType::Code {
+            json_get::<Vec<JsonValue>>(&mut cell, "", "outputs")?
+                .into_iter()
+                .enumerate()
+                .map(|(i, output)| Output::from_json(output, &format!("outputs[{}]", i)))
+                .collect()
+        } else {
+            vec![]
+        };
+        Ok(Cell {
+            cell_type,
+            source: json_lines(&mut cell, "", "source")?,
+            metadata: match cell.remove("metadata") {
+                Some(metadata) => json_object(metadata, "metadata")?,
+                None => HashMap::new(),
+            },
+            attachments,
+            // Cells that were never run have `"execution_count": null`.
+            execution_count: match cell.remove("execution_count") {
+                Some(JsonValue::Number(n)) => Some(n as u64),
+                _ => None,
+            },
+            outputs,
+            fingerprint: None,
+        })
+    }
+
+    /// Look up a value in the cell's metadata, following `path` through nested objects.
+    pub fn metadata_value(&self, path: &[&str]) -> Option<&JsonValue> {
+        let (first, rest) = path.split_first()?;
+        let mut value = self.metadata.get(*first)?;
+        for key in rest {
+            value = value
+                .get::<HashMap<String, JsonValue>>()
+                .and_then(|o| o.get(*key))?;
+        }
+        Some(value)
+    }
+
+    /// Look up a boolean flag in the cell's metadata. Missing keys count as `false`.
+    pub fn metadata_flag(&self, path: &[&str]) -> bool {
+        self.metadata_value(path)
+            .and_then(|v| v.get::<bool>())
+            .copied()
+            .unwrap_or(false)
+    }
+
+    /// The tags in `metadata.tags`.
+    pub fn tags(&self) -> Vec<&str> {
+        self.metadata_value(&["tags"])
+            .and_then(|t| t.get::<Vec<JsonValue>>())
+            .map(|tags| {
+                tags.iter()
+                    .filter_map(|t| t.get::<String>())
+                    .map(String::as_str)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+pub enum Output {
+    Stream {
+        name: String,
+        text: String,
+    },
+    ExecuteResult(MimeBundle),
+    DisplayData(MimeBundle),
+    Error {
+        ename: String,
+        evalue: String,
+        traceback: Vec<String>,
+    },
+}
+
+impl Output {
+    /// Read the output `json`, found at `path` in its cell.
+    pub fn from_json(json: JsonValue, path: &str) -> Result<Output, J2TError> {
+        let mut output = json_object(json, path)?;
+        let data = |mut output: HashMap<String, JsonValue>| {
+            let data = json_take(&mut output, path, "data")?;
+            MimeBundle::from_json(data, &format!("{}.data", path))
+        };
+        match json_get::<String>(&mut output, path, "output_type")?.as_str() {
+            "stream" => Ok(Output::Stream {
+                name: json_get(&mut output, path, "name")?,
+                text: json_lines(&mut output, path, "text")?,
+            }),
+            "execute_result" => Ok(Output::ExecuteResult(data(output)?)),
+            "display_data" => Ok(Output::DisplayData(data(output)?)),
+            "error" => Ok(Output::Error {
+                ename: json_get(&mut output, path, "ename")?,
+                evalue: json_get(&mut output, path, "evalue")?,
+                traceback: json_get::<Vec<JsonValue>>(&mut output, path, "traceback")?
+                    .into_iter()
+                    .map(String::try_from)
+                    .collect::<Result<_, _>>()?,
+            }),
+            other => Err(J2TError {
+                msg: Some(format!("`{}`: unknown output type `{}`", path, other)),
+                ..Default::default()
+            }),
+        }
+    }
+
+    /// The data of `execute_result` and `display_data` outputs.
+    pub fn data(&self) -> Option<&MimeBundle> {
+        match self {
+            Output::ExecuteResult(data) | Output::DisplayData(data) => Some(data),
+            _ => None,
+        }
+    }
+}
+
+/// The representations of an output or attachment, keyed by MIME type. Text and base64-encoded
+/// binary data is stored as is; JSON data is stored serialized.
+#[derive(Debug, Clone, Default)]
+pub struct MimeBundle {
+    pub data: HashMap<String, String>,
+}
+
+impl MimeBundle {
+    /// Read the bundle `json`, found at `path` in its cell.
+    pub fn from_json(json: JsonValue, path: &str) -> Result<MimeBundle, J2TError> {
+        let mut data = HashMap::new();
+        for (mime, value) in json_object(json, path)? {
+            let value = match value {
+                JsonValue::String(_) | JsonValue::Array(_) => join_json_lines_array(value),
+                _ => value.stringify().map_err(|e| J2TError {
+                    msg: Some(format!("`{}.{}`: {}", path, mime, e)),
+                    ..Default::default()
+                }),
+            }?;
+            data.insert(mime, value);
+        }
+        Ok(MimeBundle { data })
+    }
+
+    pub fn get(&self, mime: &str) -> Option<&str> {
+        self.data.get(mime).map(String::as_str)
+    }
+
+    /// The MIME types in the bundle, sorted.
+    pub fn mime_types(&self) -> Vec<&str> {
+        let mut mimes = self.data.keys().map(String::as_str).collect::<Vec<_>>();
+        mimes.sort();
+        mimes
+    }
+}
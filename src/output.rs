@@ -0,0 +1,331 @@
+//! Rendering of code cell outputs.
+
+use crate::assets;
+use crate::document::write_typst_string;
+use crate::markdown::convert_markdown_to_typst;
+use crate::notebook::{Cell, Output};
+use crate::render::Context;
+use crate::tableize::Table;
+use crate::J2TError;
+
+use std::borrow::Cow;
+use std::fmt::{self, Write};
+
+/// Handling of lines wider than the page, in code or outputs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Wrap {
+    /// Leave lines as they are; Typst only breaks them at spaces.
+    Off,
+    /// Let the block styling also break lines after punctuation.
+    Soft,
+    /// Break lines at the given column, marking continuations.
+    Hard(usize),
+}
+
+impl std::str::FromStr for Wrap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(Wrap::Off),
+            "soft" => Ok(Wrap::Soft),
+            _ => match s.strip_prefix("hard:").map(str::parse) {
+                Some(Ok(column)) if column > 0 => Ok(Wrap::Hard(column)),
+                _ => Err(format!(
+                    "unknown wrap mode '{}' (expected 'soft', 'hard:N' or 'off')",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+impl Wrap {
+    /// The argument to pass to `codeblock`/`resultblock`. Only soft wrapping is done by the
+    /// template, so that custom templates needn't support it otherwise.
+    pub fn block_argument(self) -> &'static str {
+        match self {
+            Wrap::Soft => "wrap: true, ",
+            _ => "",
+        }
+    }
+
+    /// Break the lines of `s` if wrapping hard.
+    pub fn apply(self, s: &str) -> Cow<'_, str> {
+        match self {
+            Wrap::Hard(column) => hard_wrap(s, column),
+            _ => Cow::Borrowed(s),
+        }
+    }
+}
+
+/// Starts lines continuing the previous one after hard wrapping.
+const CONTINUATION_MARKER: &str = "\u{21aa} ";
+
+/// Break lines of `s` longer than `column` characters, starting each continuation with
+/// `CONTINUATION_MARKER`.
+fn hard_wrap(s: &str, column: usize) -> Cow<'_, str> {
+    if s.lines().all(|l| l.chars().count() <= column) {
+        return Cow::Borrowed(s);
+    }
+    let marker_width = CONTINUATION_MARKER.chars().count();
+    let mut wrapped = String::with_capacity(s.len() + s.len() / column * 4);
+    for (i, line) in s.split('\n').enumerate() {
+        if i > 0 {
+            wrapped.push('\n');
+        }
+        let mut chars = line.chars().peekable();
+        let mut width = column;
+        loop {
+            wrapped.extend(chars.by_ref().take(width));
+            if chars.peek().is_none() {
+                break;
+            }
+            wrapped.push('\n');
+            wrapped.push_str(CONTINUATION_MARKER);
+            // Continuations including the marker fit into `column` too, if it is wide enough.
+            width = column.saturating_sub(marker_width).max(1);
+        }
+    }
+    Cow::Owned(wrapped)
+}
+
+/// Write the Typst expression showing the result of `cell` into `out`.
+pub fn format_cell_result(ctx: &Context, cell: &Cell, out: &mut dyn Write) -> Result<(), J2TError> {
+    let mut outputs = vec![];
+    for (i, output) in cell.outputs.iter().enumerate() {
+        match output {
+            Ok(output) => outputs.push((i, output)),
+            // Skip malformed outputs, keeping the indices of the others.
+            Err(e) if !ctx.opts.strict => ctx.warn(Some(i), format!("skipped: {}", e)),
+            Err(e) => {
+                return Err(J2TError {
+                    msg: Some(e.to_string()),
+                    ..Default::default()
+                })
+            }
+        }
+    }
+
+    for (i, output) in outputs.iter() {
+        if let Some(data) = output.data() {
+            if !ctx.opts.mime_priority.iter().any(|m| data.get(m).is_some()) {
+                ctx.unsupported(
+                    Some(*i),
+                    format!("unsupported MIME type(s) {}", data.mime_types().join(", ")),
+                )?;
+            }
+        }
+    }
+
+    let figure = Figure::of(cell);
+    // Prefer data in the most preferred MIME type, with `execute_result` before `display_data`.
+    for mime in ctx.opts.mime_priority.iter() {
+        for execute_result in [true, false] {
+            for (i, output) in outputs.iter() {
+                let data = match output {
+                    Output::ExecuteResult(data) if execute_result => data,
+                    Output::DisplayData(data) if !execute_result => data,
+                    _ => continue,
+                };
+                if let Some(e) = data.get(mime) {
+                    let formatted = match figure {
+                        Some(ref figure) if assets::image_extension(mime).is_some() => {
+                            let mut image = String::new();
+                            format_output_data(ctx, *i, mime, e, &mut image)
+                                .and_then(|()| figure.write(ctx, *i, &image, out))
+                        }
+                        _ => format_output_data(ctx, *i, mime, e, out),
+                    };
+                    let formatted = formatted.map_err(|e| J2TError {
+                        msg: Some(format!(
+                            "`outputs[{}].data.{}`: {}",
+                            i,
+                            mime,
+                            e.msg.as_deref().unwrap_or("invalid data")
+                        )),
+                        ..e
+                    });
+                    match formatted {
+                        Ok(()) => return Ok(()),
+                        Err(e) if !ctx.opts.strict => ctx.warn(Some(*i), format!("skipped: {}", e)),
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
+        }
+    }
+
+    // Then use stream / stderr.
+    for (i, output) in outputs.iter() {
+        if let Output::Stream { ref text, .. } = output {
+            write_output_text(ctx, *i, text, out)?;
+            return Ok(());
+        }
+    }
+
+    write_raw_text(out, "")?;
+    Ok(())
+}
+
+/// Caption and label for the image output of a cell, which is then shown as a numbered figure.
+struct Figure {
+    caption: Option<String>,
+    label: Option<String>,
+}
+
+impl Figure {
+    /// The figure settings of `cell`, from `metadata.caption` and `metadata.label`, a
+    /// `caption:...` tag, or Quarto-style `#| fig-cap:` and `#| label:` comments.
+    fn of(cell: &Cell) -> Option<Figure> {
+        let option = |key: &str| {
+            cell.source
+                .lines()
+                .take_while(|l| l.starts_with("#|"))
+                .filter_map(|l| l[2..].split_once(':'))
+                .find(|(k, _)| k.trim() == key)
+                .map(|(_, v)| v.trim().trim_matches('"').to_string())
+        };
+        let metadata = |key: &str| {
+            cell.metadata_value(&[key])
+                .and_then(|v| v.get::<String>())
+                .cloned()
+        };
+        let caption = metadata("caption")
+            .or_else(|| {
+                cell.tags()
+                    .into_iter()
+                    .find_map(|t| t.strip_prefix("caption:"))
+                    .map(|c| c.trim().to_string())
+            })
+            .or_else(|| option("fig-cap"));
+        let label = metadata("label").or_else(|| option("label"));
+        if caption.is_none() && label.is_none() {
+            return None;
+        }
+        Some(Figure { caption, label })
+    }
+
+    /// Write the expression `image` of output `output` as a figure into `out`.
+    fn write(
+        &self,
+        ctx: &Context,
+        output: usize,
+        image: &str,
+        out: &mut dyn Write,
+    ) -> Result<(), J2TError> {
+        // A label can only be attached in markup.
+        write!(out, "[#figure({}", image)?;
+        if let Some(ref caption) = self.caption {
+            let caption = convert_markdown_to_typst(ctx, caption)?;
+            write!(out, ", caption: [{}]", caption.trim())?;
+        }
+        out.write_str(")")?;
+        match self.label {
+            Some(ref label)
+                if label
+                    .chars()
+                    .all(|c| c.is_alphanumeric() || "_-.:".contains(c)) =>
+            {
+                write!(out, " <{}>", label)?
+            }
+            Some(ref label) => {
+                ctx.unsupported(Some(output), format!("invalid figure label `{}`", label))?
+            }
+            None => {}
+        }
+        out.write_str("]")?;
+        Ok(())
+    }
+}
+
+/// Write the Typst expression for output `output`, given as `text` of MIME type `mime`, into
+/// `out`. Images are stored as assets. Nothing is written if this fails.
+pub fn format_output_data(
+    ctx: &Context,
+    output: usize,
+    mime: &str,
+    text: &str,
+    out: &mut dyn Write,
+) -> Result<(), J2TError> {
+    match assets::image_extension(mime) {
+        Some(extension) => {
+            let path = store_image(ctx, mime, extension, text)?;
+            out.write_str("image(")?;
+            write_typst_string(out, &path)?;
+            out.write_str(")")?;
+        }
+        // Markdown generated by code, e.g. with `IPython.display.Markdown`.
+        None if mime == "text/markdown" => {
+            let markup = convert_markdown_to_typst(ctx, text)?;
+            write!(out, "[{}]", markup.trim())?;
+        }
+        // DataFrame reprs, if HTML wasn't available or preferred.
+        None if mime == "text/plain" && ctx.opts.tableize_output => {
+            match Table::parse(&strip_ansi_codes(text)) {
+                Some(table) => table.write_typst(out)?,
+                None => write_output_text(ctx, output, text, out)?,
+            }
+        }
+        None => write_output_text(ctx, output, text, out)?,
+    }
+    Ok(())
+}
+
+/// Store image data of MIME type `mime`, which is base64-encoded unless it is SVG, and return
+/// the path to refer to it by.
+pub fn store_image(
+    ctx: &Context,
+    mime: &str,
+    extension: &str,
+    data: &str,
+) -> Result<String, J2TError> {
+    let path = if mime == "image/svg+xml" {
+        ctx.assets.store(data.as_bytes(), extension)?
+    } else {
+        let bytes = assets::decode_base64(data).map_err(|e| J2TError {
+            msg: Some(format!("{} data: {}", mime, e)),
+            ..Default::default()
+        })?;
+        ctx.assets.store(&bytes, extension)?
+    };
+    ctx.stored_assets.borrow_mut().push(path.clone());
+    Ok(path)
+}
+
+/// Write the text of output `output` for `resultblock`, cleaned up, truncated and wrapped.
+fn write_output_text(ctx: &Context, output: usize, text: &str, out: &mut dyn Write) -> fmt::Result {
+    let text = strip_ansi_codes(text);
+    let text = truncate_output(ctx, output, &text);
+    write_raw_text(out, &ctx.opts.wrap_output.apply(&text))
+}
+
+/// Write `s` as text to be shown verbatim by `resultblock`.
+pub fn write_raw_text(out: &mut dyn Write, s: &str) -> fmt::Result {
+    write_typst_string(out, s)
+}
+
+/// Cut the text of output `output` down to `max_output_lines` lines, noting how many were
+/// dropped.
+pub fn truncate_output<'a>(ctx: &Context, output: usize, s: &'a str) -> Cow<'a, str> {
+    let max = match ctx.opts.max_output_lines {
+        Some(max) => max,
+        None => return Cow::Borrowed(s),
+    };
+    let total = s.lines().count();
+    if total <= max {
+        return Cow::Borrowed(s);
+    }
+    ctx.warn(
+        Some(output),
+        format!("truncated output from {} to {} lines", total, max),
+    );
+    let mut truncated = s.lines().take(max).collect::<Vec<_>>().join("\n");
+    write!(truncated, "\n[... {} more lines]", total - max).unwrap();
+    Cow::Owned(truncated)
+}
+
+pub fn strip_ansi_codes(s: &str) -> Cow<'_, str> {
+    // TODO: implement this functionality.
+    Cow::Borrowed(s)
+}
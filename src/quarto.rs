@@ -0,0 +1,371 @@
+//! Reading Quarto documents (`.qmd`) as notebooks.
+//!
+//! The YAML front matter provides the title, authors and date. Executable code blocks, written as
+//! ```` ```{python} ````, become code cells; everything in between becomes Markdown cells. Cell
+//! options given as `#| key: value` comments at the top of a code block are removed from the
+//! code, and `echo`, `output` and `include` are mapped to the `remove-*` tags.
+//!
+//! Documents have no outputs of their own. Figures that Quarto rendered into the `<name>_files`
+//! directory next to the document are picked up as the outputs of their cells. Frozen results in
+//! `_freeze` only hold the rendered Markdown of the whole document and are not used.
+
+use crate::assets::{encode_base64, image_extension};
+use crate::yaml;
+use crate::J2TError;
+
+use tinyjson::JsonValue;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Convert the Quarto document `source`, read from `path`, into nbformat 4 JSON.
+pub fn parse(source: &str, path: &Path) -> Result<JsonValue, J2TError> {
+    let (front_matter, body) = split_front_matter(source);
+    let mut metadata = match front_matter {
+        Some(front_matter) => front_matter_metadata(front_matter)?,
+        None => HashMap::new(),
+    };
+
+    let figures = Figures::find(path);
+    let mut cells = vec![];
+    let mut language = None;
+    let mut markdown = vec![];
+    let mut lines = body.lines();
+    while let Some(line) = lines.next() {
+        let (fence, lang) = match executable_fence(line) {
+            Some(fence) => fence,
+            None => {
+                markdown.push(line);
+                continue;
+            }
+        };
+        cells.extend(markdown_cell(&markdown));
+        markdown.clear();
+        language.get_or_insert_with(|| lang.to_string());
+
+        let code = lines
+            .by_ref()
+            .take_while(|l| l.trim_end() != fence)
+            .collect::<Vec<_>>();
+        cells.push(code_cell(&code, cells.len() + 1, &figures));
+    }
+    cells.extend(markdown_cell(&markdown));
+
+    if let Some(language) = language {
+        let mut language_info = HashMap::new();
+        language_info.insert("name".to_string(), language.into());
+        metadata.insert("language_info".to_string(), language_info.into());
+    }
+
+    let mut nb = HashMap::new();
+    nb.insert("nbformat".to_string(), JsonValue::Number(4.0));
+    nb.insert("nbformat_minor".to_string(), JsonValue::Number(5.0));
+    nb.insert("metadata".to_string(), metadata.into());
+    nb.insert("cells".to_string(), JsonValue::Array(cells));
+    Ok(nb.into())
+}
+
+/// Split `source` into the YAML front matter between `---` lines, if any, and the rest.
+pub fn split_front_matter(source: &str) -> (Option<&str>, &str) {
+    let rest = match source
+        .strip_prefix("---\n")
+        .or_else(|| source.strip_prefix("---\r\n"))
+    {
+        Some(rest) => rest,
+        None => return (None, source),
+    };
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        if line.trim_end() == "---" {
+            return (Some(&rest[..offset]), &rest[offset + line.len()..]);
+        }
+        offset += line.len();
+    }
+    (None, source)
+}
+
+/// Notebook metadata from the front matter: `title`, `authors` and `date`.
+fn front_matter_metadata(front_matter: &str) -> Result<HashMap<String, JsonValue>, J2TError> {
+    let yaml = yaml::parse(front_matter).map_err(|e| J2TError {
+        msg: Some(format!("front matter: {}", e)),
+        ..Default::default()
+    })?;
+    let mut yaml = match yaml {
+        JsonValue::Object(yaml) => yaml,
+        _ => HashMap::new(),
+    };
+    let mut metadata = HashMap::new();
+    for key in ["title", "date"] {
+        if let Some(JsonValue::String(value)) = yaml.remove(key) {
+            metadata.insert(key.to_string(), value.into());
+        }
+    }
+    // Quarto accepts a single author or a list, each a name or an object with a `name`.
+    let authors = match yaml.remove("author").or_else(|| yaml.remove("authors")) {
+        Some(JsonValue::Array(authors)) => authors,
+        Some(author) => vec![author],
+        None => vec![],
+    };
+    if !authors.is_empty() {
+        metadata.insert("authors".to_string(), JsonValue::Array(authors));
+    }
+    Ok(metadata)
+}
+
+/// If `line` opens an executable code block like ```` ```{python} ````, the closing fence and
+/// the language.
+fn executable_fence(line: &str) -> Option<(&str, &str)> {
+    let line = line.trim_end();
+    let ticks = line.len() - line.trim_start_matches('`').len();
+    if ticks < 3 {
+        return None;
+    }
+    let lang = line[ticks..].trim().strip_prefix('{')?.strip_suffix('}')?;
+    // Options may follow the language: {python echo=false}
+    let lang = lang.split([' ', ',']).next().unwrap_or_default();
+    if lang.is_empty() || lang.starts_with('.') || lang.starts_with('=') {
+        return None;
+    }
+    Some((&line[..ticks], lang))
+}
+
+fn markdown_cell(lines: &[&str]) -> Option<JsonValue> {
+    let text = lines.join("\n");
+    if text.trim().is_empty() {
+        return None;
+    }
+    let mut cell = HashMap::new();
+    cell.insert("cell_type".to_string(), "markdown".to_string().into());
+    cell.insert("source".to_string(), text.trim().to_string().into());
+    cell.insert("metadata".to_string(), JsonValue::Object(HashMap::new()));
+    Some(cell.into())
+}
+
+/// The code cell for the block `lines`, which is cell `number` of the document.
+fn code_cell(lines: &[&str], number: usize, figures: &Figures) -> JsonValue {
+    let options = lines
+        .iter()
+        .take_while(|l| l.starts_with("#|"))
+        .filter_map(|l| l[2..].split_once(':'))
+        .map(|(k, v)| (k.trim(), v.trim()))
+        .collect::<HashMap<_, _>>();
+    let code = lines
+        .iter()
+        .skip_while(|l| l.starts_with("#|"))
+        .copied()
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let mut tags = vec![];
+    for (option, tag) in [
+        ("include", "remove-cell"),
+        ("echo", "remove-input"),
+        ("output", "remove-output"),
+    ] {
+        if options.get(option) == Some(&"false") {
+            tags.push(JsonValue::String(tag.to_string()));
+        }
+    }
+    let mut metadata = HashMap::new();
+    if !tags.is_empty() {
+        metadata.insert("tags".to_string(), JsonValue::Array(tags));
+    }
+    // Shown as figure caption and label, see `output::Figure`.
+    for (option, key) in [("fig-cap", "caption"), ("label", "label")] {
+        if let Some(value) = options.get(option) {
+            let value = value.trim_matches('"').to_string();
+            metadata.insert(key.to_string(), value.into());
+        }
+    }
+
+    let label = options.get("label").copied();
+    let outputs = figures
+        .for_cell(label, number)
+        .into_iter()
+        .map(|(mime, data)| {
+            let mut bundle = HashMap::new();
+            bundle.insert(mime.to_string(), data.into());
+            let mut output = HashMap::new();
+            output.insert("output_type".to_string(), "display_data".to_string().into());
+            output.insert("data".to_string(), bundle.into());
+            output.insert("metadata".to_string(), JsonValue::Object(HashMap::new()));
+            output.into()
+        })
+        .collect();
+
+    let mut cell = HashMap::new();
+    cell.insert("cell_type".to_string(), "code".to_string().into());
+    cell.insert("source".to_string(), code.into());
+    cell.insert("metadata".to_string(), metadata.into());
+    cell.insert("execution_count".to_string(), JsonValue::Null);
+    cell.insert("outputs".to_string(), JsonValue::Array(outputs));
+    cell.into()
+}
+
+/// Figures rendered by Quarto into `<name>_files/figure-<format>/`.
+struct Figures {
+    files: Vec<PathBuf>,
+}
+
+impl Figures {
+    fn find(path: &Path) -> Figures {
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let dir = path.with_file_name(format!("{}_files", stem));
+        let mut files = fs::read_dir(dir)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter(|e| e.file_name().to_string_lossy().starts_with("figure-"))
+            .flat_map(|e| fs::read_dir(e.path()).into_iter().flatten().flatten())
+            .map(|e| e.path())
+            .collect::<Vec<_>>();
+        files.sort();
+        Figures { files }
+    }
+
+    /// The figures of the cell with `label`, or otherwise of cell `number`, named
+    /// `<label>-output-<n>` or `cell-<number>-output-<n>`, as MIME type and notebook data.
+    fn for_cell(&self, label: Option<&str>, number: usize) -> Vec<(&'static str, String)> {
+        let prefix = match label {
+            Some(label) => format!("{}-output-", label),
+            None => format!("cell-{}-output-", number),
+        };
+        self.files
+            .iter()
+            .filter(|f| {
+                f.file_name()
+                    .map_or(false, |n| n.to_string_lossy().starts_with(&prefix))
+            })
+            .filter_map(|f| {
+                let extension = f.extension()?.to_str()?;
+                let mime = ["image/png", "image/jpeg", "image/svg+xml"]
+                    .into_iter()
+                    .find(|m| image_extension(m) == Some(extension))?;
+                let data = fs::read(f).ok()?;
+                if mime == "image/svg+xml" {
+                    Some((mime, String::from_utf8(data).ok()?))
+                } else {
+                    Some((mime, encode_base64(&data)))
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Cell type, source and tags of the cells of `nb`.
+    fn cells(nb: &HashMap<String, JsonValue>) -> Vec<(String, String, Vec<JsonValue>)> {
+        let cells: &Vec<JsonValue> = nb["cells"].get().unwrap();
+        cells
+            .iter()
+            .map(|cell| {
+                let cell: &HashMap<String, JsonValue> = cell.get().unwrap();
+                let metadata: &HashMap<String, JsonValue> = cell["metadata"].get().unwrap();
+                let tags = match metadata.get("tags") {
+                    Some(JsonValue::Array(tags)) => tags.clone(),
+                    _ => vec![],
+                };
+                let cell_type: &String = cell["cell_type"].get().unwrap();
+                let source: &String = cell["source"].get().unwrap();
+                (cell_type.clone(), source.clone(), tags)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn front_matter() {
+        assert_eq!(
+            split_front_matter("---\ntitle: x\n---\nbody\n"),
+            (Some("title: x\n"), "body\n")
+        );
+        assert_eq!(split_front_matter("body\n"), (None, "body\n"));
+        assert_eq!(
+            split_front_matter("---\nunclosed\n"),
+            (None, "---\nunclosed\n")
+        );
+    }
+
+    #[test]
+    fn fences() {
+        assert_eq!(executable_fence("```{python}"), Some(("```", "python")));
+        assert_eq!(executable_fence("````{r echo=false}"), Some(("````", "r")));
+        assert_eq!(executable_fence("```python"), None);
+        assert_eq!(executable_fence("```{.python}"), None);
+    }
+
+    #[test]
+    fn document() {
+        let source = "---
+title: Report
+author:
+  - name: Ada
+---
+
+# Intro
+
+```{python}
+#| echo: false
+#| label: setup
+import numpy as np
+```
+
+Text with ```` ```{python} ```` inline.
+
+```{r}
+x <- 1
+```
+";
+        let nb = parse(source, Path::new("report.qmd")).unwrap();
+        let nb: &HashMap<String, JsonValue> = nb.get().unwrap();
+        let metadata: &HashMap<String, JsonValue> = nb["metadata"].get().unwrap();
+        assert_eq!(metadata["title"], JsonValue::String("Report".to_string()));
+        assert!(matches!(&metadata["authors"], JsonValue::Array(a) if a.len() == 1));
+        let language_info: &HashMap<String, JsonValue> = metadata["language_info"].get().unwrap();
+        assert_eq!(
+            language_info["name"],
+            JsonValue::String("python".to_string())
+        );
+        assert_eq!(
+            cells(nb),
+            [
+                ("markdown".to_string(), "# Intro".to_string(), vec![]),
+                (
+                    "code".to_string(),
+                    "import numpy as np".to_string(),
+                    vec![JsonValue::String("remove-input".to_string())]
+                ),
+                (
+                    "markdown".to_string(),
+                    "Text with ```` ```{python} ```` inline.".to_string(),
+                    vec![]
+                ),
+                ("code".to_string(), "x <- 1".to_string(), vec![]),
+            ]
+        );
+    }
+
+    #[test]
+    fn figures() {
+        let dir = std::env::temp_dir().join(format!("jupyter2typst-quarto-{}", std::process::id()));
+        let figures = dir.join("doc_files").join("figure-html");
+        fs::create_dir_all(&figures).unwrap();
+        fs::write(figures.join("cell-1-output-1.png"), b"foo").unwrap();
+        fs::write(figures.join("plot-output-1.svg"), "<svg/>").unwrap();
+
+        let figures = Figures::find(&dir.join("doc.qmd"));
+        assert_eq!(
+            figures.for_cell(None, 1),
+            [("image/png", "Zm9v".to_string())]
+        );
+        assert_eq!(
+            figures.for_cell(Some("plot"), 2),
+            [("image/svg+xml", "<svg/>".to_string())]
+        );
+        assert!(figures.for_cell(None, 2).is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,114 @@
+//! Notebooks downloaded over HTTP. `https://` (and `http://`) URLs are fetched as they are;
+//! `jupyter://host/api/contents/path` is read through the contents API of a Jupyter Server,
+//! authenticated with a token. Only the latter receives the token. `jupyter://` uses HTTP for
+//! servers on the local machine and HTTPS otherwise; `jupyter+http://` and `jupyter+https://`
+//! choose explicitly.
+
+use crate::notebook::{is_loopback_host, json_get, json_object, json_take, parse_notebook_source};
+use crate::{upgrade, J2TError};
+
+use tinyjson::JsonValue;
+
+use std::io::Read;
+use std::path::Path;
+
+/// Schemes of notebooks on a Jupyter Server, with the scheme used to reach the server, if fixed.
+pub const JUPYTER_SCHEMES: [(&str, Option<&str>); 3] = [
+    ("jupyter://", None),
+    ("jupyter+http://", Some("http")),
+    ("jupyter+https://", Some("https")),
+];
+
+/// Download the notebook at `url` and parse it like a file of the same name.
+pub fn fetch(url: &str, token: Option<&str>) -> Result<JsonValue, J2TError> {
+    let path = url_path(url);
+    let url = match contents_url(url) {
+        Some(url) => url,
+        None => return parse_notebook_source(&get(url, None)?, Path::new(path)),
+    };
+    let model = get(&url, token)?.parse()?;
+
+    // The contents model: https://jupyter-server.readthedocs.io/en/latest/developers/contents.html
+    let mut model = json_object(model, "")?;
+    let kind: String = json_get(&mut model, "", "type")?;
+    let content = json_take(&mut model, "", "content")?;
+    match (kind.as_str(), content) {
+        ("notebook", nb) => Ok(upgrade::upgrade(nb)),
+        ("file", JsonValue::String(source)) => parse_notebook_source(&source, Path::new(path)),
+        _ => Err(J2TError {
+            msg: Some(format!("{} is not a notebook or text file", url)),
+            ..Default::default()
+        }),
+    }
+}
+
+/// The contents API URL of the notebook at the `jupyter://` (or `jupyter+http(s)://`) `url`, or
+/// `None` for other URLs.
+fn contents_url(url: &str) -> Option<String> {
+    let (location, scheme) = JUPYTER_SCHEMES
+        .iter()
+        .find_map(|(prefix, scheme)| Some((url.strip_prefix(prefix)?, *scheme)))?;
+    // Servers on the local machine usually don't have a certificate.
+    let host = location.split(['/', '?']).next().unwrap_or_default();
+    let scheme = scheme.unwrap_or(if is_loopback_host(host) {
+        "http"
+    } else {
+        "https"
+    });
+    let separator = if location.contains('?') { '&' } else { '?' };
+    Some(format!("{}://{}{}content=1", scheme, location, separator))
+}
+
+/// Send a GET request for `url` and return the response body.
+fn get(url: &str, token: Option<&str>) -> Result<String, J2TError> {
+    debug!("GET {}", url);
+    let mut request = ureq::get(url);
+    if let Some(token) = token {
+        request = request.set("Authorization", &format!("token {}", token));
+    }
+    let response = request.call().map_err(|e| J2TError {
+        msg: Some(e.to_string()),
+        ..Default::default()
+    })?;
+    // `into_string` is limited to 10 MB, which notebooks with images easily exceed.
+    let mut body = String::new();
+    response.into_reader().read_to_string(&mut body)?;
+    Ok(body)
+}
+
+/// The path of `url`, without scheme, host, query and fragment.
+fn url_path(url: &str) -> &str {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let path = rest.find('/').map_or("", |i| &rest[i..]);
+    path.split(['?', '#']).next().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn contents_urls() {
+        assert_eq!(
+            contents_url("jupyter://localhost:8888/api/contents/a.ipynb").as_deref(),
+            Some("http://localhost:8888/api/contents/a.ipynb?content=1")
+        );
+        assert_eq!(
+            contents_url("jupyter://[::1]:8888/api/contents/a.ipynb").as_deref(),
+            Some("http://[::1]:8888/api/contents/a.ipynb?content=1")
+        );
+        assert_eq!(
+            contents_url("jupyter://localhost.example.com/api/contents/a.ipynb").as_deref(),
+            Some("https://localhost.example.com/api/contents/a.ipynb?content=1")
+        );
+        assert_eq!(
+            contents_url("jupyter+http://hub:8000/api/contents/a.ipynb?x=1").as_deref(),
+            Some("http://hub:8000/api/contents/a.ipynb?x=1&content=1")
+        );
+        assert_eq!(
+            contents_url("jupyter+https://127.0.0.1/api/contents/a.ipynb").as_deref(),
+            Some("https://127.0.0.1/api/contents/a.ipynb?content=1")
+        );
+        assert_eq!(contents_url("https://example.com/a.ipynb"), None);
+    }
+}
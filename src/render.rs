@@ -0,0 +1,611 @@
+//! Assembling complete documents from notebooks.
+
+use crate::assets::Assets;
+use crate::cache::{self, CellCache};
+use crate::cell::format_cell;
+#[cfg(feature = "pdf")]
+use crate::compile;
+use crate::config::Options;
+use crate::diagnostics::{Diagnostic, Severity};
+use crate::document::{format_timestamp, typst_string, DocumentInfo, DocumentOverrides, Layout};
+use crate::math::Macros;
+use crate::notebook::{
+    notebook_language, notebook_name, notebook_overview, read_notebook, MimeBundle, Notebook,
+};
+use crate::{check, diagnostics, filter, log, notebook, split, templates, J2TError, STDIO_PATH};
+
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use tinyjson::JsonValue;
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+#[cfg(feature = "pdf")]
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+pub struct Context<'a> {
+    pub opts: &'a Options,
+    pub lang: String,
+    pub file: String,
+    /// Where extracted images are stored.
+    pub assets: &'a Assets,
+    /// Added to the level of Markdown headings, to nest them below chapter headings.
+    pub heading_offset: usize,
+    /// LaTeX macros used in math.
+    pub macros: &'a Macros,
+    /// Attachments of the Markdown cell being converted.
+    pub attachments: RefCell<HashMap<String, MimeBundle>>,
+    /// Index of the cell being converted.
+    pub cell: Cell<Option<usize>>,
+    /// Warnings about content that could not be converted faithfully.
+    pub warnings: RefCell<Vec<Diagnostic>>,
+    /// Paths of the images stored for the current cell.
+    pub stored_assets: RefCell<Vec<String>>,
+    /// Kinds of Markdown nodes reported as unsupported in the current cell.
+    pub unsupported_nodes: RefCell<Vec<&'static str>>,
+}
+
+impl Context<'_> {
+    /// Record a warning about the current cell and, optionally, one of its outputs.
+    pub fn warn(&self, output: Option<usize>, reason: String) {
+        let warning = Diagnostic {
+            severity: Severity::Warning,
+            file: self.file.clone(),
+            cell: self.cell.get(),
+            output,
+            reason,
+        };
+        warn!("{}", warning);
+        self.warnings.borrow_mut().push(warning);
+    }
+
+    /// Report content that can't be converted: an error in strict mode, otherwise a warning.
+    pub fn unsupported(&self, output: Option<usize>, reason: String) -> Result<(), J2TError> {
+        if !self.opts.strict {
+            self.warn(output, reason);
+            return Ok(());
+        }
+        Err(J2TError {
+            msg: Some(match output {
+                Some(output) => format!("output {}: {}", output, reason),
+                None => reason,
+            }),
+            ..Default::default()
+        })
+    }
+}
+
+/// Validate `infile` and convert it without writing any output, collecting all problems and
+/// warnings. Also returns the exit status if the notebook is invalid or fails to convert.
+pub fn check_notebook(
+    opts: Result<Options, J2TError>,
+    infile: &str,
+    overrides: &DocumentOverrides,
+) -> (Vec<Diagnostic>, Option<i32>) {
+    let token = opts.as_ref().ok().and_then(|opts| opts.token.as_deref());
+    let nb = match read_notebook(infile, token) {
+        Ok(nb) => nb,
+        Err(e) => {
+            return (
+                vec![Diagnostic::error(infile, e.to_string())],
+                Some(e.exit_code()),
+            )
+        }
+    };
+    let problems = check::validate(&nb);
+    // Conversion is bound to fail on structural problems; only report those.
+    if !problems.is_empty() {
+        let problems = problems
+            .into_iter()
+            .map(|p| Diagnostic {
+                cell: p.cell,
+                ..Diagnostic::error(infile, p.msg)
+            })
+            .collect();
+        return (problems, Some(diagnostics::EXIT_PARSE));
+    }
+    let render = |opts: Options| {
+        let nb = load_notebook(&opts, nb)?;
+        let assets = Assets::dry_run(opts.assets_dir.as_deref(), infile);
+        render_notebook(&opts, nb, infile, &assets, overrides, &mut io::sink())
+    };
+    match opts.and_then(render) {
+        Ok(warnings) => (warnings, None),
+        Err(e) => (
+            vec![Diagnostic::error(infile, e.to_string())],
+            Some(e.exit_code()),
+        ),
+    }
+}
+
+/// Convert the notebook `infile` into the Typst file `outfile`, or into a PDF or SVG file if
+/// compilation was requested. Returns the warnings emitted during conversion.
+pub fn convert_notebook(
+    opts: Options,
+    infile: &str,
+    outfile: &str,
+    overrides: &DocumentOverrides,
+) -> Result<Vec<Diagnostic>, J2TError> {
+    let nb = load_notebook(&opts, read_notebook(infile, opts.token.as_deref())?)?;
+    let assets = Assets::new(opts.assets_dir.as_deref(), infile, outfile);
+    write_output(&opts, outfile, &assets, |out| {
+        render_notebook(&opts, nb, infile, &assets, overrides, out)
+    })
+}
+
+/// Convert the notebooks `infiles` into a single document written to `outfile`.
+pub fn merge_notebooks(
+    opts: Options,
+    infiles: &[String],
+    outfile: &str,
+    overrides: &DocumentOverrides,
+) -> Result<Vec<Diagnostic>, J2TError> {
+    let notebooks = infiles
+        .iter()
+        .map(|infile| {
+            let nb = load_notebook(&opts, read_notebook(infile, opts.token.as_deref())?)?;
+            Ok((infile.as_str(), nb))
+        })
+        .collect::<Result<Vec<_>, J2TError>>()?;
+    let assets = Assets::new(opts.assets_dir.as_deref(), &infiles[0], outfile);
+    write_output(&opts, outfile, &assets, |out| {
+        render_merged(&opts, notebooks, outfile, &assets, overrides, out)
+    })
+}
+
+/// Build the notebook model from `json`, passing its cells through the cell filter if one is set.
+pub fn load_notebook(opts: &Options, json: JsonValue) -> Result<Notebook, J2TError> {
+    let json = match opts.cell_filter {
+        Some(ref cmd) => filter::filter_cells(cmd, json)?,
+        None => json,
+    };
+    // Hashing cells, including their images, is only worth it for the cache.
+    let fingerprints = if opts.cache {
+        cache::cell_fingerprints(&json)
+    } else {
+        vec![]
+    };
+    let mut nb = Notebook::from_json(json)?;
+    for (cell, fingerprint) in nb.cells.iter_mut().zip(fingerprints) {
+        if let Ok(cell) = cell {
+            cell.fingerprint = Some(fingerprint);
+        }
+    }
+    Ok(nb)
+}
+
+/// Pass the generated Typst `source` through the post filter, if one is set.
+pub fn post_filter(opts: &Options, source: Vec<u8>) -> Result<Vec<u8>, J2TError> {
+    match opts.post_filter {
+        Some(ref cmd) => filter::run(cmd, source),
+        None => Ok(source),
+    }
+}
+
+/// Write the Typst source produced by `render` into `outfile`, after the post filter, and
+/// compiled if `opts.compile` is set, or split into chapters if `opts.split_by_heading` is.
+/// `assets` are those referred to by the source.
+pub fn write_output<F>(
+    opts: &Options,
+    outfile: &str,
+    assets: &Assets,
+    render: F,
+) -> Result<Vec<Diagnostic>, J2TError>
+where
+    F: FnOnce(&mut dyn io::Write) -> Result<Vec<Diagnostic>, J2TError>,
+{
+    if opts.split_by_heading.is_some() {
+        if outfile == STDIO_PATH || opts.compile {
+            return Err(J2TError {
+                msg: Some("--split-by-heading requires a Typst output file".to_string()),
+                ..Default::default()
+            });
+        }
+        let mut source = vec![];
+        let warnings = render(&mut source)?;
+        let source = String::from_utf8(post_filter(opts, source)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        split::write_chapters(&source, outfile)?;
+        return Ok(warnings);
+    }
+    if outfile == STDIO_PATH {
+        let mut out = io::stdout().lock();
+        let warnings = render_output(opts, outfile, assets, render, &mut out)?;
+        out.flush()?;
+        return Ok(warnings);
+    }
+    // The output is written into a temporary file, which replaces `outfile` once complete, so
+    // that a failed conversion or compilation leaves an existing output alone.
+    let temp = format!("{}.tmp", outfile);
+    let result = fs::File::create(&temp)
+        .map_err(J2TError::from)
+        .and_then(|file| {
+            let mut out = io::BufWriter::new(file);
+            let warnings = render_output(opts, outfile, assets, render, &mut out)?;
+            out.flush()?;
+            Ok(warnings)
+        });
+    match result {
+        Ok(_) => fs::rename(&temp, outfile)?,
+        Err(_) => {
+            let _ = fs::remove_file(&temp);
+        }
+    }
+    result
+}
+
+/// Write the Typst source produced by `render` into `out`, after the post filter, and compiled
+/// if `opts.compile` is set.
+fn render_output<F>(
+    opts: &Options,
+    outfile: &str,
+    assets: &Assets,
+    render: F,
+    out: &mut dyn io::Write,
+) -> Result<Vec<Diagnostic>, J2TError>
+where
+    F: FnOnce(&mut dyn io::Write) -> Result<Vec<Diagnostic>, J2TError>,
+{
+    if !opts.compile && opts.post_filter.is_none() {
+        return render(out);
+    }
+    let mut source = vec![];
+    let warnings = render(&mut source)?;
+    let source = post_filter(opts, source)?;
+    if opts.compile {
+        out.write_all(&compile_output(source, outfile, assets)?)?;
+    } else {
+        out.write_all(&source)?;
+    }
+    Ok(warnings)
+}
+
+/// Compile `source`, whose asset paths are relative to `outfile`, into the format given by the
+/// extension of `outfile`.
+#[cfg(feature = "pdf")]
+pub fn compile_output(
+    source: Vec<u8>,
+    outfile: &str,
+    assets: &Assets,
+) -> Result<Vec<u8>, J2TError> {
+    let source =
+        String::from_utf8(source).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let format = compile::Format::from_path(Path::new(outfile)).unwrap_or(compile::Format::Pdf);
+    let (root, main) = compile_root(outfile, assets)?;
+    compile::compile(source, &root, &main, format)
+}
+
+#[cfg(not(feature = "pdf"))]
+pub fn compile_output(
+    _source: Vec<u8>,
+    _outfile: &str,
+    _assets: &Assets,
+) -> Result<Vec<u8>, J2TError> {
+    Err(J2TError {
+        msg: Some("--pdf requires jupyter2typst to be built with the `pdf` feature".to_string()),
+        ..Default::default()
+    })
+}
+
+/// The directory Typst may read files from when compiling `outfile`, and the path of `outfile`
+/// in it. The assets may be stored outside of the output's directory (`--assets-dir`), so this is
+/// the closest directory containing both.
+#[cfg(feature = "pdf")]
+fn compile_root(outfile: &str, assets: &Assets) -> Result<(PathBuf, PathBuf), J2TError> {
+    let out_dir = match Path::new(outfile).parent() {
+        Some(dir) if outfile != STDIO_PATH && !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let out_dir = fs::canonicalize(out_dir)?;
+    let mut root = out_dir.clone();
+    // The assets directory only exists once something was stored.
+    if let Ok(dir) = fs::canonicalize(assets.dir()) {
+        while !dir.starts_with(&root) && root.pop() {}
+    }
+    let main = out_dir
+        .strip_prefix(&root)
+        .unwrap_or(Path::new(""))
+        .join("main.typ");
+    Ok((root, main))
+}
+
+/// Write the Typst source for the notebook `nb`, read from `infile`, into `outfile`.
+/// Returns the warnings about content that could not be converted faithfully.
+pub fn render_notebook(
+    opts: &Options,
+    nb: Notebook,
+    infile: &str,
+    assets: &Assets,
+    overrides: &DocumentOverrides,
+    outfile: &mut dyn io::Write,
+) -> Result<Vec<Diagnostic>, J2TError> {
+    let language = notebook_language(opts, &nb.metadata, infile);
+    let mut info = DocumentInfo::from_metadata(&nb.metadata, infile);
+    overrides.apply(&mut info);
+
+    write_preamble(opts, &language, &notebook_name(infile), &info, outfile)?;
+    let warnings = render_cells(opts, assets, nb, infile, language, 0, outfile)?;
+    write_bibliography(opts, assets, outfile)?;
+    Ok(warnings)
+}
+
+/// Write a single document containing all `notebooks`, given with the files they were read
+/// from, into `outfile`. Each notebook becomes a chapter, titled like the notebook.
+pub fn render_merged(
+    opts: &Options,
+    notebooks: Vec<(&str, Notebook)>,
+    outfile_name: &str,
+    assets: &Assets,
+    overrides: &DocumentOverrides,
+    outfile: &mut dyn io::Write,
+) -> Result<Vec<Diagnostic>, J2TError> {
+    let mut chapters = vec![];
+    let mut languages = vec![];
+    for (infile, nb) in notebooks.iter() {
+        chapters.push(DocumentInfo::from_metadata(&nb.metadata, infile));
+        languages.push(notebook_language(opts, &nb.metadata, infile));
+    }
+    let mut authors: Vec<String> = vec![];
+    for author in chapters.iter().flat_map(|c| c.authors.iter()) {
+        if !authors.contains(author) {
+            authors.push(author.clone());
+        }
+    }
+    let mut info = DocumentInfo {
+        title: if outfile_name == STDIO_PATH {
+            String::new()
+        } else {
+            Path::new(outfile_name)
+                .file_stem()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        },
+        authors,
+        date: chapters.iter().filter_map(|c| c.date).max(),
+    };
+    overrides.apply(&mut info);
+
+    let names = notebooks
+        .iter()
+        .map(|(infile, _)| notebook_name(infile))
+        .collect::<Vec<_>>()
+        .join(", ");
+    write_preamble(opts, &languages[0], &names, &info, outfile)?;
+
+    let mut warnings = vec![];
+    for (((infile, nb), chapter), language) in notebooks.into_iter().zip(chapters).zip(languages) {
+        if opts.split_by_heading.is_some() {
+            outfile.write_all(split::CHAPTER_MARKER.as_bytes())?;
+        }
+        write!(
+            outfile,
+            "#pagebreak(weak: true)\n#heading(level: 1, {})\n\n",
+            typst_string(&chapter.title)
+        )?;
+        warnings.extend(render_cells(
+            opts, assets, nb, infile, language, 1, outfile,
+        )?);
+    }
+    write_bibliography(opts, assets, outfile)?;
+    Ok(warnings)
+}
+
+/// Write the bibliography, if one was given. The file is stored with the assets, so that it is
+/// found relative to the document.
+fn write_bibliography(
+    opts: &Options,
+    assets: &Assets,
+    outfile: &mut dyn io::Write,
+) -> Result<(), J2TError> {
+    let bibliography = match opts.bibliography {
+        Some(ref bibliography) => bibliography,
+        None => return Ok(()),
+    };
+    let data = fs::read(bibliography).map_err(|e| J2TError {
+        msg: Some(format!("bibliography {}", bibliography)),
+        ..J2TError::from(e)
+    })?;
+    // Typst tells BibTeX from Hayagriva YAML files by their extension.
+    let extension = Path::new(bibliography)
+        .extension()
+        .map_or("bib".into(), |e| e.to_string_lossy());
+    let path = assets.store(&data, &extension)?;
+    write!(
+        outfile,
+        "
+#bibliography({})
+",
+        typst_string(&path)
+    )?;
+    Ok(())
+}
+
+/// The current time. `SystemTime::now` panics in WebAssembly, so the `wasm` build asks
+/// JavaScript.
+fn now() -> SystemTime {
+    #[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+    return std::time::UNIX_EPOCH + std::time::Duration::from_millis(js_sys::Date::now() as u64);
+    #[cfg(not(all(feature = "wasm", target_arch = "wasm32")))]
+    SystemTime::now()
+}
+
+/// Write the theme, template definitions, page setup, title block and outline.
+pub fn write_preamble(
+    opts: &Options,
+    lang: &str,
+    notebook_name: &str,
+    info: &DocumentInfo,
+    outfile: &mut dyn io::Write,
+) -> Result<(), J2TError> {
+    let theme = templates::theme(&opts.theme).ok_or_else(|| J2TError {
+        msg: Some(format!(
+            "unknown theme '{}' (available: {})",
+            opts.theme,
+            templates::THEMES
+                .iter()
+                .map(|t| t.name)
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+        ..Default::default()
+    })?;
+
+    if opts.split_by_heading.is_some() {
+        outfile.write_all(split::DEFINITIONS_START.as_bytes())?;
+    }
+    outfile.write_all(theme.source.as_bytes())?;
+    outfile.write_all(opts.style.to_typst().as_bytes())?;
+    let vars = templates::TemplateVars::new(
+        lang,
+        notebook_name,
+        &info.title,
+        &info.date.map(|d| d.to_string()).unwrap_or_default(),
+        &format_timestamp(now()),
+        opts.prompts,
+    );
+    let root = match opts.template {
+        Some(ref template) => fs::read_to_string(template)
+            .map_err(J2TError::from)
+            .and_then(|source| templates::render_template(template, &source, &vars)),
+        None => templates::render_template("<built-in>", templates::DOCUMENT_ROOT, &vars),
+    };
+    outfile.write_all(root?.as_bytes())?;
+    if opts.layout == Layout::TwoColumn {
+        outfile.write_all(templates::LAYOUT_TWOCOL.as_bytes())?;
+        if opts.template.is_none() {
+            outfile.write_all(templates::LAYOUT_TWOCOL_NOTES.as_bytes())?;
+        }
+    }
+    outfile.write_all(opts.page.to_typst().as_bytes())?;
+    outfile.write_all(info.to_typst().as_bytes())?;
+    if opts.toc {
+        write!(outfile, "#outline(depth: {})\n\n", opts.toc_depth)?;
+    }
+    if opts.split_by_heading.is_some() {
+        outfile.write_all(split::DEFINITIONS_END.as_bytes())?;
+    }
+    Ok(())
+}
+
+/// Write the cells of the notebook `nb`, with Markdown headings moved down by
+/// `heading_offset` levels.
+pub fn render_cells(
+    opts: &Options,
+    assets: &Assets,
+    nb: Notebook,
+    infile: &str,
+    lang: String,
+    heading_offset: usize,
+    outfile: &mut dyn io::Write,
+) -> Result<Vec<Diagnostic>, J2TError> {
+    notebook_overview(&nb);
+    let macros = Macros::collect(opts, &nb)?;
+    let definitions = macros.to_typst();
+    if opts.split_by_heading.is_some() {
+        // All chapters need the macros.
+        write!(
+            outfile,
+            "{}{}{}",
+            split::DEFINITIONS_START,
+            definitions,
+            split::DEFINITIONS_END
+        )?;
+    } else {
+        outfile.write_all(definitions.as_bytes())?;
+    }
+    let cache = CellCache::new(
+        opts,
+        assets,
+        &format!("{}\n{}\n{}", lang, heading_offset, definitions),
+    );
+    if opts.layout == Layout::TwoColumn {
+        outfile.write_all(b"#twocol[\n")?;
+    }
+
+    let progress = log::Progress::new(nb.cells.len());
+    // Cells are converted independently, each with its own context. A cell is rendered into a
+    // buffer first, so that a cell failing halfway leaves no partial markup behind.
+    let convert = |(i, cell): (usize, Result<notebook::Cell, J2TError>)| {
+        let cached = match (&cache, &cell) {
+            (Some(cache), Ok(cell)) => cell.fingerprint.and_then(|f| cache.get(f)),
+            _ => None,
+        };
+        if let Some(out) = cached {
+            progress.tick();
+            return Ok((out, vec![]));
+        }
+        let fingerprint = cell.as_ref().ok().and_then(|c| c.fingerprint);
+        let ctx = Context {
+            opts,
+            lang: lang.clone(),
+            file: infile.to_string(),
+            assets,
+            heading_offset,
+            macros: &macros,
+            attachments: RefCell::new(HashMap::new()),
+            cell: Cell::new(Some(i)),
+            warnings: RefCell::new(vec![]),
+            stored_assets: RefCell::new(vec![]),
+            unsupported_nodes: RefCell::new(vec![]),
+        };
+        let mut out = String::new();
+        match cell.and_then(|cell| format_cell(&ctx, cell, &mut out)) {
+            Ok(()) => {
+                // Cells with warnings are converted again, so that the warnings are repeated.
+                if let (Some(cache), Some(fingerprint)) = (&cache, fingerprint) {
+                    if ctx.warnings.borrow().is_empty() {
+                        cache.put(fingerprint, &out, &ctx.stored_assets.borrow());
+                    }
+                }
+            }
+            Err(e) => {
+                if ctx.opts.strict {
+                    return Err(J2TError { cell: Some(i), ..e });
+                }
+                let e = J2TError { cell: None, ..e };
+                ctx.warn(None, format!("could not be converted: {}", e));
+                out = failed_cell_box(i);
+            }
+        }
+        progress.tick();
+        Ok((out, ctx.warnings.into_inner()))
+    };
+
+    // Converted cells are written out batch by batch, so that memory use is bounded by the size
+    // of a batch rather than the whole document.
+    let mut cells = nb.cells.into_iter().enumerate();
+    let mut warnings = vec![];
+    loop {
+        let batch = cells.by_ref().take(CELL_BATCH_SIZE).collect::<Vec<_>>();
+        if batch.is_empty() {
+            break;
+        }
+        #[cfg(feature = "parallel")]
+        let batch = batch.into_par_iter().map(convert);
+        #[cfg(not(feature = "parallel"))]
+        let batch = batch.into_iter().map(convert);
+        for (cell, cell_warnings) in batch.collect::<Result<Vec<_>, J2TError>>()? {
+            outfile.write_all(cell.as_bytes())?;
+            warnings.extend(cell_warnings);
+        }
+    }
+    if opts.layout == Layout::TwoColumn {
+        outfile.write_all(b"]\n")?;
+    }
+    Ok(warnings)
+}
+
+/// Number of cells converted before their output is written.
+const CELL_BATCH_SIZE: usize = 64;
+
+/// Shown in place of a cell that could not be converted in lenient mode.
+fn failed_cell_box(index: usize) -> String {
+    format!(
+        "\n#block(fill: rgb(\"fdecea\"), stroke: rgb(\"d93025\"), inset: 8pt, radius: 3pt, width: 100%)[⚠ cell {} could not be converted]\n",
+        index
+    )
+}
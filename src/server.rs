@@ -0,0 +1,282 @@
+//! Conversion over HTTP (`--serve`), so that editor extensions can convert notebooks repeatedly
+//! without starting a process each time.
+//!
+//! `POST /convert` takes a JSON object with these keys:
+//!
+//! - `notebook`: the notebook JSON, or the text of a notebook, Quarto document or script file.
+//! - `path` (optional): the name of the file the notebook was read from. Only its file name is
+//!   used, for the default title and to tell how a notebook given as text is parsed.
+//! - `options` (optional): options with the keys of the configuration file that only affect
+//!   rendering (`config::REQUEST_KEYS`), e.g. `{"theme": "report", "pdf": true}`. Keys naming
+//!   files, like `template` or `bibliography`, are rejected.
+//!
+//! Requests start out with the options of the server: its command line flags and its
+//! configuration file (`--config`, or `jupyter2typst.toml` in its working directory).
+//!
+//! The response is a JSON object with the Typst source (`typst`), the base64-encoded PDF (`pdf`)
+//! if the `pdf` option is set, and the warnings (`diagnostics`, as in `--report`). Errors are
+//! returned with a 4xx status as `{"error": "..."}`. Images are written into `assets/` in a
+//! temporary directory owned by the server, whose path is returned as `root`; the Typst source
+//! refers to them relative to it.
+//!
+//! Any process on the machine, and any web page by DNS rebinding, may send requests. So the
+//! server only listens on loopback addresses and answers requests for a loopback `Host`, unless
+//! told to serve other machines with `--serve-public`.
+
+use crate::assets::{encode_base64, Assets};
+use crate::notebook::{is_loopback_host, json_object, json_take, parse_notebook_source};
+use crate::render::{compile_output, load_notebook, post_filter, render_notebook};
+use crate::{upgrade, DocumentOverrides, J2TError, Options, STDIO_PATH};
+
+use tinyjson::JsonValue;
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use std::{env, process};
+
+/// Largest request body accepted.
+const MAX_BODY_SIZE: usize = 256 << 20;
+/// How long to wait for a client to send its request.
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Serve conversions at `addr` until the process is stopped. The options for each request start
+/// out as returned by `load_options`. Unless `public` is set, `addr` must be a loopback address.
+pub fn serve(
+    addr: &str,
+    public: bool,
+    load_options: &dyn Fn() -> Result<Options, J2TError>,
+    overrides: &DocumentOverrides,
+) -> Result<(), J2TError> {
+    if !public && !addr.to_socket_addrs()?.all(|a| a.ip().is_loopback()) {
+        return Err(J2TError {
+            msg: Some(format!(
+                "{} is not a loopback address; use --serve-public to serve other machines",
+                addr
+            )),
+            ..Default::default()
+        });
+    }
+    let listener = TcpListener::bind(addr)?;
+    let server = Server {
+        public,
+        load_options,
+        overrides,
+        root: env::temp_dir().join(format!("jupyter2typst-serve-{}", process::id())),
+    };
+    fs::create_dir_all(&server.root)?;
+    info!("Listening on http://{}/convert", listener.local_addr()?);
+    info!("Writing images into {}", server.root.display());
+    for stream in listener.incoming() {
+        let result = stream.map_err(J2TError::from).and_then(|stream| {
+            stream.set_read_timeout(Some(READ_TIMEOUT))?;
+            server.handle(stream)
+        });
+        if let Err(e) = result {
+            warn!("{}", e);
+        }
+    }
+    Ok(())
+}
+
+struct Server<'a> {
+    public: bool,
+    load_options: &'a dyn Fn() -> Result<Options, J2TError>,
+    overrides: &'a DocumentOverrides,
+    /// Directory of the documents, into which their images are written.
+    root: PathBuf,
+}
+
+impl Server<'_> {
+    fn handle(&self, stream: TcpStream) -> Result<(), J2TError> {
+        let mut reader = BufReader::new(&stream);
+        let result = read_request(&mut reader, &stream).and_then(|request| {
+            debug!("{} {}", request.method, request.path);
+            if !self.public && !request.host.as_deref().map_or(false, is_loopback_host) {
+                return Err(Failure::new(
+                    "403 Forbidden",
+                    "only requests for localhost are served",
+                ));
+            }
+            match (request.method.as_str(), request.path.as_str()) {
+                ("POST", "/convert") => self.convert(&request.body),
+                (_, "/convert") => Err(Failure::new("405 Method Not Allowed", "use POST")),
+                (_, path) => Err(Failure::new(
+                    "404 Not Found",
+                    format!("no such path {}", path),
+                )),
+            }
+        });
+        let (status, body) = match result {
+            Ok(response) => ("200 OK", response),
+            Err(failure) => {
+                let mut error = HashMap::new();
+                error.insert("error".to_string(), failure.msg.into());
+                (failure.status, JsonValue::from(error))
+            }
+        };
+        let body = body.stringify().map_err(|e| J2TError {
+            msg: Some(e.to_string()),
+            ..Default::default()
+        })?;
+        let mut stream = &stream;
+        write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            status,
+            body.len()
+        )?;
+        stream.write_all(body.as_bytes())?;
+        stream.flush()?;
+        Ok(())
+    }
+
+    /// Convert the notebook in the request `body` into the response object.
+    fn convert(&self, body: &[u8]) -> Result<JsonValue, Failure> {
+        let bad_request = |e: J2TError| Failure::new("400 Bad Request", e);
+        let failed = |e: J2TError| Failure::new("422 Unprocessable Entity", e);
+
+        let body = std::str::from_utf8(body).map_err(|e| Failure::new("400 Bad Request", e))?;
+        let mut request = body
+            .parse()
+            .map_err(J2TError::from)
+            .and_then(|request| json_object(request, "request"))
+            .map_err(bad_request)?;
+        // Only the file name is used, so that requests can't read or write files elsewhere.
+        let infile = match request.remove("path") {
+            Some(JsonValue::String(path)) => match Path::new(&path).file_name() {
+                Some(name) => name.to_string_lossy().into_owned(),
+                None => STDIO_PATH.to_string(),
+            },
+            None | Some(JsonValue::Null) => STDIO_PATH.to_string(),
+            Some(_) => return Err(Failure::new("400 Bad Request", "`path` must be a string")),
+        };
+        let nb = match json_take(&mut request, "", "notebook").map_err(bad_request)? {
+            JsonValue::String(source) => {
+                parse_notebook_source(&source, Path::new(&infile)).map_err(failed)?
+            }
+            nb => upgrade::upgrade(nb),
+        };
+        let mut opts = (self.load_options)().map_err(bad_request)?;
+        if let Some(options) = request.remove("options") {
+            opts.apply_request_json(options).map_err(bad_request)?;
+        }
+        let nb = load_notebook(&opts, nb).map_err(failed)?;
+
+        // The document is placed in the server's directory, with the images in `assets/`.
+        let outfile = self
+            .root
+            .join("notebook.typ")
+            .to_string_lossy()
+            .into_owned();
+        let assets_dir = self.root.join("assets").to_string_lossy().into_owned();
+        let assets = Assets::new(Some(&assets_dir), &infile, &outfile);
+        let mut source = vec![];
+        let warnings = render_notebook(&opts, nb, &infile, &assets, self.overrides, &mut source)
+            .map_err(failed)?;
+        let source = post_filter(&opts, source).map_err(failed)?;
+        let typst =
+            String::from_utf8(source).map_err(|e| Failure::new("422 Unprocessable Entity", e))?;
+
+        let mut response = HashMap::new();
+        if opts.compile {
+            let pdf =
+                compile_output(typst.clone().into_bytes(), &outfile, &assets).map_err(failed)?;
+            response.insert("pdf".to_string(), encode_base64(&pdf).into());
+        }
+        response.insert("typst".to_string(), typst.into());
+        response.insert(
+            "root".to_string(),
+            self.root.to_string_lossy().into_owned().into(),
+        );
+        response.insert(
+            "diagnostics".to_string(),
+            JsonValue::Array(warnings.iter().map(|d| d.to_json()).collect()),
+        );
+        Ok(response.into())
+    }
+}
+
+struct Request {
+    method: String,
+    path: String,
+    /// The `Host` header, if sent.
+    host: Option<String>,
+    body: Vec<u8>,
+}
+
+/// An HTTP status with the message returned to the client.
+struct Failure {
+    status: &'static str,
+    msg: String,
+}
+
+impl Failure {
+    fn new(status: &'static str, msg: impl ToString) -> Failure {
+        Failure {
+            status,
+            msg: msg.to_string(),
+        }
+    }
+}
+
+/// Read a request with its body from `reader`. `stream` is the connection, for confirming an
+/// `Expect: 100-continue` header.
+fn read_request(reader: &mut dyn BufRead, mut stream: &TcpStream) -> Result<Request, Failure> {
+    let bad_request = |msg: &str| Failure::new("400 Bad Request", msg);
+    let io_failure = |e: io::Error| bad_request(&e.to_string());
+
+    let mut line = String::new();
+    reader.read_line(&mut line).map_err(io_failure)?;
+    let mut parts = line.split_whitespace();
+    let (method, path) = match (parts.next(), parts.next()) {
+        (Some(method), Some(path)) => (method.to_string(), path.to_string()),
+        _ => return Err(bad_request("malformed request line")),
+    };
+
+    let mut length = 0;
+    let mut host = None;
+    let mut expect_continue = false;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).map_err(io_failure)? == 0 {
+            return Err(bad_request("unexpected end of request"));
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        let (name, value) = header
+            .split_once(':')
+            .ok_or_else(|| bad_request("malformed header"))?;
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("content-length") {
+            length = value
+                .parse()
+                .map_err(|_| bad_request("invalid Content-Length"))?;
+        } else if name.eq_ignore_ascii_case("host") {
+            host = Some(value.to_string());
+        } else if name.eq_ignore_ascii_case("expect") {
+            expect_continue = value.eq_ignore_ascii_case("100-continue");
+        }
+    }
+    if length > MAX_BODY_SIZE {
+        return Err(Failure::new("413 Payload Too Large", "request too large"));
+    }
+    if expect_continue {
+        stream
+            .write_all(b"HTTP/1.1 100 Continue\r\n\r\n")
+            .map_err(io_failure)?;
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).map_err(io_failure)?;
+    Ok(Request {
+        method,
+        path,
+        host,
+        body,
+    })
+}
@@ -0,0 +1,134 @@
+//! Splitting a generated document into chapter files (`--split-by-heading`).
+//!
+//! While rendering, definitions (the preamble and LaTeX macros) are enclosed in marker comments,
+//! and a marker precedes each heading starting a chapter. The master file `report.typ` keeps the
+//! preamble and includes `report-01.typ`, `report-02.typ`, ... in order. As included files don't
+//! see the master's definitions, the chapters import them from `report-defs.typ`.
+
+use crate::document::typst_string;
+use crate::J2TError;
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+pub const DEFINITIONS_START: &str = "// jupyter2typst: definitions\n";
+pub const DEFINITIONS_END: &str = "// jupyter2typst: end of definitions\n";
+pub const CHAPTER_MARKER: &str = "// jupyter2typst: chapter\n";
+
+/// Write `source`, containing the markers, as the master file `outfile` and its chapters next to
+/// it.
+pub fn write_chapters(source: &str, outfile: &str) -> Result<(), J2TError> {
+    let mut definitions = String::new();
+    let mut body = String::new();
+    let mut rest = source;
+    while let Some(start) = rest.find(DEFINITIONS_START) {
+        body.push_str(&rest[..start]);
+        rest = &rest[start + DEFINITIONS_START.len()..];
+        let end = rest.find(DEFINITIONS_END).unwrap_or(rest.len());
+        definitions.push_str(&rest[..end]);
+        rest = rest[end..]
+            .strip_prefix(DEFINITIONS_END)
+            .unwrap_or_default();
+    }
+    body.push_str(rest);
+
+    let path = Path::new(outfile);
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let sibling = |suffix: &str| {
+        let name = format!("{}-{}.typ", stem, suffix);
+        (path.with_file_name(&name), name)
+    };
+
+    let (defs_path, defs_name) = sibling("defs");
+    fs::write(&defs_path, &definitions)?;
+
+    let mut master = io::BufWriter::new(fs::File::create(path)?);
+    master.write_all(definitions.as_bytes())?;
+    let chapters = body
+        .split(CHAPTER_MARKER)
+        .filter(|c| !c.trim().is_empty())
+        .collect::<Vec<_>>();
+    // Enough digits for the files to sort in order.
+    let width = chapters.len().to_string().len().max(2);
+    for (i, chapter) in chapters.iter().enumerate() {
+        let (chapter_path, chapter_name) = sibling(&format!("{:0width$}", i + 1, width = width));
+        let mut file = io::BufWriter::new(fs::File::create(&chapter_path)?);
+        writeln!(file, "#import {}: *", typst_string(&defs_name))?;
+        file.write_all(chapter.as_bytes())?;
+        file.flush()?;
+        writeln!(master, "#include {}", typst_string(&chapter_name))?;
+    }
+    master.flush()?;
+    debug!("{}: split into {} chapters", outfile, chapters.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chapters() {
+        let dir = std::env::temp_dir().join(format!("jupyter2typst-split-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let outfile = dir.join("report.typ");
+        let mut source = format!("{}#let x = 1\n{}", DEFINITIONS_START, DEFINITIONS_END);
+        source.push_str("Title\n");
+        for i in 1..=10 {
+            source.push_str(CHAPTER_MARKER);
+            source.push_str(&format!("= Chapter {}\n", i));
+            if i == 2 {
+                source.push_str(&format!(
+                    "{}#let y = 2\n{}",
+                    DEFINITIONS_START, DEFINITIONS_END
+                ));
+            }
+        }
+        write_chapters(&source, &outfile.to_string_lossy()).unwrap();
+
+        let read = |name: &str| fs::read_to_string(dir.join(name)).unwrap();
+        assert_eq!(read("report-defs.typ"), "#let x = 1\n#let y = 2\n");
+        let master = read("report.typ");
+        assert!(master.starts_with("#let x = 1\n#let y = 2\n#include \"report-01.typ\"\n"));
+        assert!(master.ends_with("#include \"report-11.typ\"\n"));
+        assert_eq!(master.matches("#include").count(), 11);
+        // Text before the first marker forms a chapter of its own.
+        assert_eq!(
+            read("report-01.typ"),
+            "#import \"report-defs.typ\": *\nTitle\n"
+        );
+        assert_eq!(
+            read("report-03.typ"),
+            "#import \"report-defs.typ\": *\n= Chapter 2\n"
+        );
+        assert_eq!(
+            read("report-11.typ"),
+            "#import \"report-defs.typ\": *\n= Chapter 10\n"
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn padding() {
+        let dir = std::env::temp_dir().join(format!(
+            "jupyter2typst-split-padding-{}",
+            std::process::id()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        let outfile = dir.join("nb.typ");
+        let source = CHAPTER_MARKER.to_string() + "= A\n" + CHAPTER_MARKER + "\n";
+        write_chapters(&source, &outfile.to_string_lossy()).unwrap();
+        // Empty chapters are dropped, and at least two digits are used.
+        assert!(dir.join("nb-01.typ").is_file());
+        assert!(!dir.join("nb-02.typ").exists());
+        assert_eq!(
+            fs::read_to_string(&outfile).unwrap(),
+            "#include \"nb-01.typ\"\n"
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,253 @@
+//! Recognition of DataFrame reprs in `text/plain` outputs, so that they can be shown as tables.
+//!
+//! pandas prints frames as fixed-width columns, with the index on the left below an empty header
+//! cell, optionally followed by a line with the index name and a `[n rows x m columns]` footer.
+//! Series have no header, but a footer with their name and dtype.
+//! polars draws box characters around its columns and names the shape and column types.
+
+use crate::document::write_typst_string;
+
+use std::fmt::{self, Write};
+
+/// A table parsed from text, with a header row.
+pub struct Table {
+    header: Vec<String>,
+    rows: Vec<Vec<String>>,
+    /// Whether the first column is the index, shown left-aligned.
+    index: bool,
+}
+
+impl Table {
+    /// Parse `text` if it looks like a pandas or polars DataFrame.
+    pub fn parse(text: &str) -> Option<Table> {
+        let lines = text
+            .lines()
+            .map(str::trim_end)
+            .filter(|l| !l.is_empty())
+            .collect::<Vec<_>>();
+        if lines.iter().any(|l| l.starts_with('┌')) {
+            parse_polars(&lines)
+        } else {
+            parse_pandas(&lines)
+        }
+    }
+
+    /// Write the `table` expression showing this table into `out`.
+    pub fn write_typst(&self, out: &mut dyn Write) -> fmt::Result {
+        let columns = self.header.len();
+        write!(out, "table(columns: {}, align: (", columns)?;
+        for i in 0..columns {
+            out.write_str(if i == 0 && self.index {
+                "left, "
+            } else {
+                "right, "
+            })?;
+        }
+        out.write_str("), table.header(")?;
+        for name in self.header.iter() {
+            out.write_str("strong(")?;
+            write_typst_string(out, name)?;
+            out.write_str("), ")?;
+        }
+        out.write_str(")")?;
+        for cell in self.rows.iter().flatten() {
+            out.write_str(", ")?;
+            write_typst_string(out, cell)?;
+        }
+        out.write_str(")")
+    }
+}
+
+fn parse_pandas(lines: &[&str]) -> Option<Table> {
+    let mut lines = lines.to_vec();
+    // `[3 rows x 2 columns]` after truncated frames.
+    if lines
+        .last()
+        .map_or(false, |l| l.starts_with('[') && l.ends_with("columns]"))
+    {
+        lines.pop();
+    }
+    // A Series has no header line, but a `Name: x, dtype: int64` footer instead.
+    let mut series_name = None;
+    if let Some(footer) = lines.last().filter(|l| l.contains("dtype: ")) {
+        let name = footer
+            .strip_prefix("Name: ")
+            .and_then(|f| f.split(", ").next())
+            .unwrap_or_default();
+        series_name = Some(name.to_string());
+        lines.pop();
+    }
+    if lines.len() < 2 {
+        return None;
+    }
+    let lines = lines
+        .iter()
+        .map(|l| l.chars().collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    let spans = column_spans(&lines);
+    if spans.len() < 2 {
+        return None;
+    }
+    let fields = |line: &[char]| {
+        spans
+            .iter()
+            .map(|&(start, end)| {
+                line.get(start..end.min(line.len()))
+                    .unwrap_or_default()
+                    .iter()
+                    .collect::<String>()
+                    .trim()
+                    .to_string()
+            })
+            .collect::<Vec<_>>()
+    };
+    let (header, rows) = match series_name {
+        Some(name) if spans.len() == 2 => {
+            let rows = lines.iter().map(|l| fields(l)).collect::<Vec<_>>();
+            (vec![String::new(), name], rows)
+        }
+        Some(_) => return None,
+        None => {
+            let mut header = fields(&lines[0]);
+            // The index has no header, and every row has an index value.
+            if !header[0].is_empty() || header[1..].iter().any(String::is_empty) {
+                return None;
+            }
+            let mut rows = lines[1..].iter().map(|l| fields(l)).collect::<Vec<_>>();
+            // A named index is printed on a line of its own below the column names.
+            if rows[0][1..].iter().all(String::is_empty) {
+                header[0] = rows.remove(0).swap_remove(0);
+            }
+            (header, rows)
+        }
+    };
+    if rows.is_empty() || rows.iter().any(|r| r[0].is_empty()) {
+        return None;
+    }
+    Some(Table {
+        header,
+        rows,
+        index: true,
+    })
+}
+
+/// The character ranges of the columns in `lines`, separated by positions that are blank in all
+/// of them.
+fn column_spans(lines: &[Vec<char>]) -> Vec<(usize, usize)> {
+    let width = lines.iter().map(Vec::len).max().unwrap_or(0);
+    let occupied = (0..width)
+        .map(|i| lines.iter().any(|l| l.get(i).map_or(false, |c| *c != ' ')))
+        .collect::<Vec<_>>();
+    let mut spans = vec![];
+    let mut start = None;
+    for (i, &occupied) in occupied.iter().chain([false].iter()).enumerate() {
+        match (start, occupied) {
+            (None, true) => start = Some(i),
+            (Some(s), false) => {
+                spans.push((s, i));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    spans
+}
+
+fn parse_polars(lines: &[&str]) -> Option<Table> {
+    let mut header = None;
+    let mut rows = vec![];
+    let mut in_body = false;
+    for line in lines {
+        if line.starts_with('╞') {
+            in_body = true;
+            continue;
+        }
+        let inner = match line.strip_prefix('│').and_then(|l| l.strip_suffix('│')) {
+            Some(inner) => inner,
+            None => continue,
+        };
+        let fields = inner
+            .split('┆')
+            .map(|f| f.trim().to_string())
+            .collect::<Vec<_>>();
+        if in_body {
+            rows.push(fields);
+        } else if header.is_none() {
+            // Below the names are a `---` line and the column types.
+            header = Some(fields);
+        }
+    }
+    let header = header?;
+    if rows.is_empty() || rows.iter().any(|r| r.len() != header.len()) {
+        return None;
+    }
+    Some(Table {
+        header,
+        rows,
+        index: false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Option<(Vec<String>, Vec<Vec<String>>, bool)> {
+        Table::parse(text).map(|t| (t.header, t.rows, t.index))
+    }
+
+    fn strings(row: &[&str]) -> Vec<String> {
+        row.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn pandas_frame() {
+        let (header, rows, index) =
+            parse("   a    b\n0  1  foo\n1  2    z\n\n[2 rows x 2 columns]\n").unwrap();
+        assert_eq!(header, strings(&["", "a", "b"]));
+        assert_eq!(
+            rows,
+            [strings(&["0", "1", "foo"]), strings(&["1", "2", "z"])]
+        );
+        assert!(index);
+    }
+
+    #[test]
+    fn named_index() {
+        let (header, rows, _) = parse("       a\nname    \nx      1\ny     22\n").unwrap();
+        assert_eq!(header, strings(&["name", "a"]));
+        assert_eq!(rows, [strings(&["x", "1"]), strings(&["y", "22"])]);
+    }
+
+    #[test]
+    fn series() {
+        let (header, rows, _) = parse("0    1.5\n1    2.0\nName: price, dtype: float64\n").unwrap();
+        assert_eq!(header, strings(&["", "price"]));
+        assert_eq!(rows, [strings(&["0", "1.5"]), strings(&["1", "2.0"])]);
+    }
+
+    #[test]
+    fn polars_frame() {
+        let text = "shape: (2, 2)
+┌─────┬─────┐
+│ a   ┆ b   │
+│ --- ┆ --- │
+│ i64 ┆ str │
+╞═════╪═════╡
+│ 1   ┆ x   │
+│ 2   ┆ y   │
+└─────┴─────┘";
+        let (header, rows, index) = parse(text).unwrap();
+        assert_eq!(header, strings(&["a", "b"]));
+        assert_eq!(rows, [strings(&["1", "x"]), strings(&["2", "y"])]);
+        assert!(!index);
+    }
+
+    #[test]
+    fn other_text() {
+        assert!(parse("Hello, world!").is_none());
+        assert!(parse("Epoch 1 loss 0.5\nEpoch 2 loss 0.4\n").is_none());
+        assert!(parse("[1, 2, 3]").is_none());
+        assert!(parse("").is_none());
+    }
+}
@@ -0,0 +1,274 @@
+//! Typst source fragments making up the preamble of generated documents.
+
+use crate::cell::Prompts;
+use crate::document::typst_escape;
+use crate::J2TError;
+
+use ramhorns::{Content, Template};
+
+/// Definitions used by the generated document body, rendered with `TemplateVars`. Expects the
+/// variables of a theme (see `THEMES`) to be defined beforehand.
+pub const DOCUMENT_ROOT: &str = r###"
+#let input_notebook = "{{{notebook_name}}}"
+#let kernel_language = "{{{lang}}}"
+#let converted_at = "{{{timestamp}}}"
+#let prompt_style = "{{{prompts}}}"
+
+#let bgcolor_graded = rgb("fff1c2")
+#let pointsnote(points) = place(
+    right, dx: 4.5em,
+    box(width: 4em, text(size: 9pt, fill: luma(100))[_#points pt._]))
+#let numbered_line(line) = {
+    box(width: 1.5em, align(right, text(size: 0.8em, fill: color_label, str(line.number))))
+    h(0.8em)
+    line.body
+}
+// Allows breaking long lines after punctuation, not only at spaces.
+#let soft_wrap(wrap, body) = {
+    show regex("[.,;:=/_)\\]}-]"): it => if wrap { it + sym.zws } else { it }
+    body
+}
+#let codeblock(
+    lang: kernel_language,
+    bgcolor: bgcolor_code,
+    line_numbers: false,
+    wrap: false,
+    code) = block(fill: bgcolor,
+                  stroke: stroke_code,
+                  outset: 5pt,
+                  radius: block_radius,
+                  width: 100%,
+                  {
+                      // Each cell is its own raw block, so numbering restarts at every cell.
+                      show raw.line: it => if line_numbers { numbered_line(it) } else { it }
+                      soft_wrap(wrap, raw(code, lang: lang))
+                  })
+#let resultblock(bgcolor: bgcolor_result, stroke: stroke_result, wrap: false, content) = block(
+    fill: bgcolor, outset: 5pt, radius: block_radius, width: 100%, stroke: stroke,
+    if type(content) == str { soft_wrap(wrap, raw(content)) } else { content })
+#let prompt_label(kind, n) = if prompt_style == "in-out" {
+    if kind == "in" { "In [" + n + "]:" } else { "Out[" + n + "]:" }
+} else if prompt_style == "numbers" {
+    "[" + n + "]"
+}
+// The execution count `n` of a code ("in") or result ("out") block, placed in the margin
+// next to the block following it.
+#let prompt(kind, n) = {
+    let label = prompt_label(kind, n)
+    if label != none {
+        place(left, dx: -5.5em, box(width: 5em, align(right,
+            text(size: 9pt, fill: color_label, raw(label)))))
+    }
+}
+
+#let admonition_colors = (
+    note: rgb("1f6feb"), tip: rgb("1a7f37"), hint: rgb("1a7f37"), important: rgb("8250df"),
+    seealso: rgb("1f6feb"), warning: rgb("9a6700"), caution: rgb("9a6700"),
+    attention: rgb("bc4c00"), danger: rgb("cf222e"), error: rgb("cf222e"))
+#let admonition(kind: "note", title: none, body) = {
+    let color = admonition_colors.at(kind, default: luma(120))
+    block(fill: color.lighten(90%), stroke: (left: 3pt + color), inset: 8pt,
+          radius: block_radius, width: 100%)[
+        #text(weight: "bold", fill: color,
+              if title == none { upper(kind.first()) + kind.slice(1) } else { title }) \
+        #body
+    ]
+}
+
+"###;
+
+/// Definitions for `Layout::TwoColumn`. The cells of each notebook are put into `twocol`, which is
+/// closed around cells wrapped in `fullwidth`. Typst doesn't balance columns, so the columns
+/// before a full-width cell aren't either: the left one is filled first. Floating the cell over
+/// both columns instead needs `place(scope: "parent")`, which the Typst version used by `--pdf`
+/// lacks.
+pub const LAYOUT_TWOCOL: &str = r###"
+#let column_gutter = 1.5em
+#let twocol(body) = columns(2, gutter: column_gutter, body)
+#let fullwidth(body) = block(width: 100%, body)
+"###;
+
+/// Follows `LAYOUT_TWOCOL` after `DOCUMENT_ROOT`. Prompts and points notes, which are otherwise
+/// placed in the page margins, would overlap the neighbouring column, so they are shown above
+/// their blocks instead. Templates replacing `DOCUMENT_ROOT` define their own.
+pub const LAYOUT_TWOCOL_NOTES: &str = r###"
+#let prompt(kind, n) = {
+    let label = prompt_label(kind, n)
+    if label != none {
+        block(below: 0.6em, text(size: 9pt, fill: color_label, raw(label)))
+    }
+}
+#let pointsnote(points) = align(right, text(size: 9pt, fill: luma(100))[_#points pt._])
+"###;
+
+/// A named set of colors, strokes and fonts. A theme defines `bgcolor_code`, `stroke_code`,
+/// `bgcolor_result`, `stroke_result`, `block_radius` and `color_label`, and may add `set`/`show`
+/// rules.
+pub struct Theme {
+    pub name: &'static str,
+    pub source: &'static str,
+}
+
+pub const THEMES: &[Theme] = &[
+    Theme {
+        name: "default",
+        source: THEME_DEFAULT,
+    },
+    Theme {
+        name: "minimal",
+        source: THEME_MINIMAL,
+    },
+    Theme {
+        name: "dark",
+        source: THEME_DARK,
+    },
+    Theme {
+        name: "report",
+        source: THEME_REPORT,
+    },
+];
+
+/// Look up a built-in theme by name.
+pub fn theme(name: &str) -> Option<&'static Theme> {
+    THEMES.iter().find(|t| t.name == name)
+}
+
+const THEME_DEFAULT: &str = r###"
+#let bgcolor_code = luma(230)
+#let stroke_code = none
+#let bgcolor_result = white
+#let stroke_result = 1pt + luma(150)
+#let color_label = luma(140)
+#let block_radius = 3pt
+"###;
+
+const THEME_MINIMAL: &str = r###"
+#let bgcolor_code = none
+#let stroke_code = (left: 2pt + luma(180))
+#let bgcolor_result = none
+#let stroke_result = (left: 1pt + luma(210))
+#let color_label = luma(170)
+#let block_radius = 3pt
+"###;
+
+const THEME_DARK: &str = r###"
+#set page(fill: rgb("1e1e2e"))
+#set text(fill: rgb("cdd6f4"))
+#let bgcolor_code = rgb("313244")
+#let stroke_code = none
+#let bgcolor_result = rgb("181825")
+#let stroke_result = 1pt + rgb("45475a")
+#let color_label = rgb("a6adc8")
+#let block_radius = 3pt
+"###;
+
+const THEME_REPORT: &str = r###"
+#set heading(numbering: "1.1")
+#show heading: set text(font: "New Computer Modern", weight: "bold")
+#let bgcolor_code = luma(245)
+#let stroke_code = 0.5pt + luma(200)
+#let bgcolor_result = white
+#let stroke_result = (left: 2pt + rgb("1f4e79"))
+#let color_label = rgb("1f4e79")
+#let block_radius = 3pt
+"###;
+
+/// Values available as `{{placeholder}}` in templates. They are escaped for use inside Typst
+/// string literals. Like in any mustache template, `{{{placeholder}}}` inserts the value without
+/// additional HTML escaping.
+#[derive(Content)]
+pub struct TemplateVars {
+    pub lang: String,
+    pub notebook_name: String,
+    pub title: String,
+    pub date: String,
+    pub timestamp: String,
+    /// Style of the execution prompts, see `Prompts`.
+    pub prompts: String,
+}
+
+impl TemplateVars {
+    pub fn new(
+        lang: &str,
+        notebook_name: &str,
+        title: &str,
+        date: &str,
+        timestamp: &str,
+        prompts: Prompts,
+    ) -> TemplateVars {
+        TemplateVars {
+            lang: typst_escape(lang),
+            notebook_name: typst_escape(notebook_name),
+            title: typst_escape(title),
+            date: typst_escape(date),
+            timestamp: typst_escape(timestamp),
+            prompts: prompts.name().to_string(),
+        }
+    }
+}
+
+/// Definitions every template must provide, as the converted cells call them.
+const REQUIRED_DEFINITIONS: &[&str] = &["codeblock", "resultblock"];
+
+/// Required unless prompts are turned off, as no `prompt` calls are emitted then.
+const PROMPT_DEFINITION: &str = "prompt";
+
+/// Interpolate `vars` into the template `source` (read from the file `name`), and check that the
+/// result defines everything the document body relies on.
+pub fn render_template(name: &str, source: &str, vars: &TemplateVars) -> Result<String, J2TError> {
+    let rendered = Template::new(source)?.render(vars);
+    let prompt = (vars.prompts != Prompts::None.name()).then_some(PROMPT_DEFINITION);
+    let missing = REQUIRED_DEFINITIONS
+        .iter()
+        .chain(prompt.iter())
+        .filter(|d| !rendered.contains(&format!("#let {}", d)))
+        .copied()
+        .collect::<Vec<_>>();
+    if !missing.is_empty() {
+        return Err(J2TError {
+            msg: Some(format!(
+                "template {} does not define {}",
+                name,
+                missing
+                    .iter()
+                    .map(|d| format!("`{}`", d))
+                    .collect::<Vec<_>>()
+                    .join(" and ")
+            )),
+            ..Default::default()
+        });
+    }
+    Ok(rendered)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(prompts: Prompts) -> TemplateVars {
+        TemplateVars::new("python", "nb", "Title", "", "", prompts)
+    }
+
+    #[test]
+    fn required_definitions() {
+        assert!(render_template("<built-in>", DOCUMENT_ROOT, &vars(Prompts::InOut)).is_ok());
+        let source = "#let codeblock(content) = content\n#let resultblock(content) = content\n";
+        assert!(render_template("t.typ", source, &vars(Prompts::None)).is_ok());
+        assert_eq!(
+            render_template("t.typ", source, &vars(Prompts::InOut))
+                .unwrap_err()
+                .to_string(),
+            "template t.typ does not define `prompt`"
+        );
+        assert_eq!(
+            render_template(
+                "t.typ",
+                "#let prompt(kind, n) = none",
+                &vars(Prompts::InOut)
+            )
+            .unwrap_err()
+            .to_string(),
+            "template t.typ does not define `codeblock` and `resultblock`"
+        );
+    }
+}
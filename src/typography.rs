@@ -0,0 +1,69 @@
+//! Smart typography for Markdown text: curly quotes and apostrophes, en and em dashes and
+//! ellipses. Typst applies this to markup itself, but not to strings like admonition titles, and
+//! templates may turn it off with `set smartquote(enabled: false)`.
+
+use std::borrow::Cow;
+
+/// Replace straight quotes, `--`, `---` and `...` in the text `s` by typographic characters.
+pub fn smart_typography(s: &str) -> Cow<'_, str> {
+    if !s.contains(['"', '\'']) && !s.contains("--") && !s.contains("...") {
+        return Cow::Borrowed(s);
+    }
+    let mut out = String::with_capacity(s.len());
+    let mut prev: Option<char> = None;
+    let mut rest = s;
+    while let Some(c) = rest.chars().next() {
+        let (replacement, len) = if rest.starts_with("---") {
+            ('\u{2014}', 3)
+        } else if rest.starts_with("--") {
+            ('\u{2013}', 2)
+        } else if rest.starts_with("...") {
+            ('\u{2026}', 3)
+        } else if c == '"' {
+            (if opens(prev) { '\u{201c}' } else { '\u{201d}' }, 1)
+        } else if c == '\'' {
+            // Apostrophes are closing single quotes.
+            (if opens(prev) { '\u{2018}' } else { '\u{2019}' }, 1)
+        } else {
+            (c, c.len_utf8())
+        };
+        out.push(replacement);
+        prev = Some(replacement);
+        rest = &rest[len..];
+    }
+    Cow::Owned(out)
+}
+
+/// Whether a quote after `prev` opens a quotation.
+fn opens(prev: Option<char>) -> bool {
+    prev.map_or(true, |c| {
+        c.is_whitespace() || "([{\u{2013}\u{2014}\u{201c}\u{2018}".contains(c)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes() {
+        assert_eq!(
+            smart_typography(r#"She said "it's 'fine'" (really)."#),
+            "She said \u{201c}it\u{2019}s \u{2018}fine\u{2019}\u{201d} (really)."
+        );
+        assert_eq!(
+            smart_typography("don't ('quote' me)"),
+            "don\u{2019}t (\u{2018}quote\u{2019} me)"
+        );
+        assert_eq!(smart_typography("\"quoted\""), "\u{201c}quoted\u{201d}");
+    }
+
+    #[test]
+    fn dashes_and_ellipses() {
+        assert_eq!(
+            smart_typography("1--2 --- or so..."),
+            "1\u{2013}2 \u{2014} or so\u{2026}"
+        );
+        assert!(matches!(smart_typography("plain - text"), Cow::Borrowed(_)));
+    }
+}
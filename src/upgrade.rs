@@ -0,0 +1,265 @@
+//! Upgrading legacy notebooks to nbformat 4, so that the rest of the converter only deals with
+//! one format.
+//!
+//! nbformat 3 notebooks keep their cells in `worksheets`, store code as `input` and execution
+//! counts as `prompt_number`, have `heading` cells, and put output data directly into the output
+//! under short names like `png` instead of into a MIME bundle.
+
+use tinyjson::JsonValue;
+
+use std::collections::HashMap;
+
+/// Short output data keys of nbformat 3 and their MIME types.
+const V3_MIME_TYPES: &[(&str, &str)] = &[
+    ("text", "text/plain"),
+    ("html", "text/html"),
+    ("svg", "image/svg+xml"),
+    ("png", "image/png"),
+    ("jpeg", "image/jpeg"),
+    ("latex", "text/latex"),
+    ("json", "application/json"),
+    ("javascript", "application/javascript"),
+];
+
+/// The major nbformat version of the notebook `nb`, if it tells.
+pub fn nbformat_version(nb: &JsonValue) -> Option<f64> {
+    nb.get::<HashMap<String, JsonValue>>()?
+        .get("nbformat")?
+        .get::<f64>()
+        .copied()
+}
+
+/// Upgrade the notebook `nb` to nbformat 4 if it is an nbformat 3 notebook. Other notebooks are
+/// returned unchanged; parts that don't have the expected structure are left for validation to
+/// report.
+pub fn upgrade(nb: JsonValue) -> JsonValue {
+    if nbformat_version(&nb) != Some(3.0) {
+        return nb;
+    }
+    let mut nb = match nb {
+        JsonValue::Object(nb) => nb,
+        nb => return nb,
+    };
+    debug!("upgrading nbformat 3 notebook");
+
+    let cells = match nb.remove("worksheets") {
+        Some(JsonValue::Array(worksheets)) => worksheets
+            .into_iter()
+            .filter_map(|ws| match ws {
+                JsonValue::Object(mut ws) => ws.remove("cells"),
+                _ => None,
+            })
+            .flat_map(|cells| match cells {
+                JsonValue::Array(cells) => cells,
+                _ => vec![],
+            })
+            .map(upgrade_cell)
+            .collect(),
+        _ => vec![],
+    };
+    nb.insert("cells".to_string(), JsonValue::Array(cells));
+    nb.insert("nbformat".to_string(), JsonValue::Number(4.0));
+    nb.insert("nbformat_minor".to_string(), JsonValue::Number(0.0));
+    nb.entry("metadata".to_string())
+        .or_insert_with(|| JsonValue::Object(HashMap::new()));
+    JsonValue::Object(nb)
+}
+
+fn upgrade_cell(cell: JsonValue) -> JsonValue {
+    let mut cell = match cell {
+        JsonValue::Object(cell) => cell,
+        cell => return cell,
+    };
+    let mut metadata = match cell.remove("metadata") {
+        Some(JsonValue::Object(metadata)) => metadata,
+        _ => HashMap::new(),
+    };
+    let cell_type = cell
+        .get("cell_type")
+        .and_then(|t| t.get::<String>())
+        .cloned();
+    match cell_type.as_deref() {
+        Some("code") => {
+            if let Some(input) = cell.remove("input") {
+                cell.insert("source".to_string(), input);
+            }
+            let execution_count = cell.remove("prompt_number").unwrap_or(JsonValue::Null);
+            cell.insert("execution_count".to_string(), execution_count);
+            cell.remove("language");
+            if let Some(collapsed) = cell.remove("collapsed") {
+                metadata.insert("collapsed".to_string(), collapsed);
+            }
+            let outputs = match cell.remove("outputs") {
+                Some(JsonValue::Array(outputs)) => {
+                    outputs.into_iter().map(upgrade_output).collect()
+                }
+                _ => vec![],
+            };
+            cell.insert("outputs".to_string(), JsonValue::Array(outputs));
+        }
+        // Heading cells become Markdown headings.
+        Some("heading") => {
+            let level = cell
+                .remove("level")
+                .and_then(|l| l.get::<f64>().copied())
+                .unwrap_or(1.0);
+            let source = match cell.remove("source") {
+                Some(JsonValue::String(s)) => s,
+                Some(JsonValue::Array(lines)) => lines
+                    .into_iter()
+                    .filter_map(|l| String::try_from(l).ok())
+                    .collect(),
+                _ => String::new(),
+            };
+            let heading = format!(
+                "{} {}",
+                "#".repeat(level.clamp(1.0, 6.0) as usize),
+                source.lines().collect::<Vec<_>>().join(" ")
+            );
+            cell.insert("cell_type".to_string(), "markdown".to_string().into());
+            cell.insert("source".to_string(), heading.into());
+        }
+        _ => (),
+    }
+    cell.insert("metadata".to_string(), JsonValue::Object(metadata));
+    JsonValue::Object(cell)
+}
+
+fn upgrade_output(output: JsonValue) -> JsonValue {
+    let mut output = match output {
+        JsonValue::Object(output) => output,
+        output => return output,
+    };
+    let output_type = output
+        .get("output_type")
+        .and_then(|t| t.get::<String>())
+        .cloned();
+    match output_type.as_deref() {
+        Some("pyout") | Some("display_data") => {
+            let mut data = HashMap::new();
+            for (key, mime) in V3_MIME_TYPES {
+                if let Some(value) = output.remove(*key) {
+                    data.insert(mime.to_string(), value);
+                }
+            }
+            output.insert("data".to_string(), JsonValue::Object(data));
+            output
+                .entry("metadata".to_string())
+                .or_insert_with(|| JsonValue::Object(HashMap::new()));
+            if output_type.as_deref() == Some("pyout") {
+                let execution_count = output.remove("prompt_number").unwrap_or(JsonValue::Null);
+                output.insert("execution_count".to_string(), execution_count);
+                output.insert(
+                    "output_type".to_string(),
+                    "execute_result".to_string().into(),
+                );
+            }
+        }
+        Some("pyerr") => {
+            output.insert("output_type".to_string(), "error".to_string().into());
+        }
+        Some("stream") => {
+            if let Some(name) = output.remove("stream") {
+                output.insert("name".to_string(), name);
+            }
+        }
+        _ => (),
+    }
+    JsonValue::Object(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const V3_NOTEBOOK: &str = r#"{
+        "nbformat": 3,
+        "nbformat_minor": 0,
+        "metadata": {"name": "legacy"},
+        "worksheets": [
+            {"cells": [
+                {"cell_type": "heading", "level": 2, "source": ["Results"], "metadata": {}},
+                {
+                    "cell_type": "code",
+                    "language": "python",
+                    "collapsed": true,
+                    "input": ["1 + 1"],
+                    "prompt_number": 3,
+                    "metadata": {},
+                    "outputs": [
+                        {"output_type": "pyout", "prompt_number": 3, "text": ["2"],
+                         "png": "iVBORw0KGgo=", "metadata": {}},
+                        {"output_type": "stream", "stream": "stdout", "text": ["hi\n"]},
+                        {"output_type": "pyerr", "ename": "ValueError", "evalue": "x",
+                         "traceback": []}
+                    ]
+                }
+            ]},
+            {"cells": [
+                {"cell_type": "markdown", "source": "Second worksheet", "metadata": {}}
+            ]}
+        ]
+    }"#;
+
+    fn object(value: &JsonValue) -> &HashMap<String, JsonValue> {
+        value.get().unwrap()
+    }
+
+    #[test]
+    fn nbformat3() {
+        let nb = upgrade(V3_NOTEBOOK.parse().unwrap());
+        let nb = object(&nb);
+        assert_eq!(nb["nbformat"], JsonValue::Number(4.0));
+        assert!(!nb.contains_key("worksheets"));
+        let cells: &Vec<JsonValue> = nb["cells"].get().unwrap();
+        assert_eq!(cells.len(), 3);
+
+        let heading = object(&cells[0]);
+        assert_eq!(heading["cell_type"], JsonValue::String("markdown".into()));
+        assert_eq!(heading["source"], JsonValue::String("## Results".into()));
+
+        let code = object(&cells[1]);
+        assert_eq!(
+            code["source"],
+            JsonValue::Array(vec![JsonValue::String("1 + 1".into())])
+        );
+        assert_eq!(code["execution_count"], JsonValue::Number(3.0));
+        assert!(!code.contains_key("input") && !code.contains_key("prompt_number"));
+        assert_eq!(
+            object(&code["metadata"])["collapsed"],
+            JsonValue::Boolean(true)
+        );
+
+        let outputs: &Vec<JsonValue> = code["outputs"].get().unwrap();
+        let result = object(&outputs[0]);
+        assert_eq!(
+            result["output_type"],
+            JsonValue::String("execute_result".into())
+        );
+        assert_eq!(result["execution_count"], JsonValue::Number(3.0));
+        let data = object(&result["data"]);
+        assert_eq!(
+            data["text/plain"],
+            JsonValue::Array(vec![JsonValue::String("2".into())])
+        );
+        assert_eq!(data["image/png"], JsonValue::String("iVBORw0KGgo=".into()));
+        assert!(!result.contains_key("png") && !result.contains_key("text"));
+
+        let stream = object(&outputs[1]);
+        assert_eq!(stream["name"], JsonValue::String("stdout".into()));
+        let error = object(&outputs[2]);
+        assert_eq!(error["output_type"], JsonValue::String("error".into()));
+
+        let markdown = object(&cells[2]);
+        assert_eq!(
+            markdown["source"],
+            JsonValue::String("Second worksheet".into())
+        );
+    }
+
+    #[test]
+    fn nbformat4_unchanged() {
+        let nb: JsonValue = r#"{"nbformat": 4, "cells": [], "metadata": {}}"#.parse().unwrap();
+        assert_eq!(upgrade(nb.clone()), nb);
+    }
+}
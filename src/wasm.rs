@@ -0,0 +1,48 @@
+//! JavaScript bindings, for running the converter in the browser. The library is only built as a
+//! `cdylib` for WebAssembly, and the bindings are generated by `wasm-bindgen`:
+//!
+//! ```sh
+//! cargo rustc --lib --release --target wasm32-unknown-unknown --no-default-features \
+//!     --features wasm --crate-type cdylib
+//! wasm-bindgen --target bundler --out-dir pkg \
+//!     target/wasm32-unknown-unknown/release/jupyter2typst.wasm
+//! ```
+//!
+//! ```js
+//! import { convert_notebook_json } from "jupyter2typst";
+//!
+//! const typst = convert_notebook_json(notebookJson, { theme: "report", page: { paper: "a5" } });
+//! ```
+
+use crate::{Converter, J2TError, Options};
+
+use wasm_bindgen::prelude::*;
+
+use std::path::Path;
+
+/// Convert the notebook JSON `json` into Typst source. `options` is `undefined` or an object
+/// with the keys of the configuration file. Images are not extracted, as there is no file system;
+/// the document refers to them in `assets_dir`, by default `notebook_assets`.
+#[wasm_bindgen]
+pub fn convert_notebook_json(json: &str, options: JsValue) -> Result<String, JsError> {
+    let convert = || -> Result<String, J2TError> {
+        let opts = js_options(options)?;
+        Converter::new(opts).without_assets().convert_str(json)
+    };
+    convert().map_err(|e| JsError::new(&e.to_string()))
+}
+
+fn js_options(options: JsValue) -> Result<Options, J2TError> {
+    let mut opts = Options::default();
+    if options.is_undefined() || options.is_null() {
+        return Ok(opts);
+    }
+    let json: String = js_sys::JSON::stringify(&options)
+        .map_err(|_| J2TError {
+            msg: Some("options: not serializable".to_string()),
+            ..Default::default()
+        })?
+        .into();
+    opts.apply_json(Path::new("options"), json.parse()?)?;
+    Ok(opts)
+}
@@ -0,0 +1,206 @@
+//! A reader for the subset of YAML found in the headers of text notebooks: nested mappings,
+//! lists written with `- `, and scalars, which may be quoted. Flow collections (`[a, b]`), anchors
+//! and multi-line scalars are not supported.
+
+use tinyjson::JsonValue;
+
+use std::collections::HashMap;
+
+/// Parse the YAML document `source` into JSON. Numbers and booleans are converted; all other
+/// scalars are kept as strings.
+pub fn parse(source: &str) -> Result<JsonValue, String> {
+    let mut lines = source
+        .lines()
+        .enumerate()
+        .filter(|(_, l)| !l.trim().is_empty() && !l.trim_start().starts_with('#'))
+        .map(|(n, l)| Line {
+            number: n + 1,
+            indent: l.len() - l.trim_start().len(),
+            text: l.trim(),
+        })
+        .collect::<Vec<_>>();
+    let mut pos = 0;
+    let value = parse_block(&mut lines, &mut pos, 0)?;
+    match lines.get(pos) {
+        Some(line) => Err(format!("line {}: unexpected indentation", line.number)),
+        None => Ok(value.unwrap_or(JsonValue::Object(HashMap::new()))),
+    }
+}
+
+struct Line<'a> {
+    number: usize,
+    indent: usize,
+    text: &'a str,
+}
+
+/// Parse the block starting at `lines[*pos]` whose lines are indented by at least `min_indent`.
+fn parse_block(
+    lines: &mut [Line],
+    pos: &mut usize,
+    min_indent: usize,
+) -> Result<Option<JsonValue>, String> {
+    let indent = match lines.get(*pos) {
+        Some(line) if line.indent >= min_indent => line.indent,
+        _ => return Ok(None),
+    };
+    if lines[*pos].text.starts_with("- ") || lines[*pos].text == "-" {
+        let mut items = vec![];
+        while let Some(line) = lines.get(*pos) {
+            if line.indent != indent || !(line.text.starts_with("- ") || line.text == "-") {
+                break;
+            }
+            let item = line.text[1..].trim();
+            if is_mapping_entry(item) {
+                // `- key: value` starts a mapping, continued by the lines aligned with `key`.
+                let item_indent = line.indent + (line.text.len() - item.len());
+                lines[*pos].indent = item_indent;
+                lines[*pos].text = item;
+                items.push(parse_block(lines, pos, item_indent)?.unwrap_or(JsonValue::Null));
+            } else if item.is_empty() {
+                *pos += 1;
+                items.push(parse_block(lines, pos, indent + 1)?.unwrap_or(JsonValue::Null));
+            } else {
+                *pos += 1;
+                items.push(scalar(item));
+            }
+        }
+        return Ok(Some(JsonValue::Array(items)));
+    }
+
+    let mut map = HashMap::new();
+    while let Some(line) = lines.get(*pos) {
+        if line.indent != indent {
+            break;
+        }
+        let (key, value) = line
+            .text
+            .split_once(':')
+            .filter(|(_, v)| v.is_empty() || v.starts_with(' '))
+            .ok_or_else(|| format!("line {}: expected `key: value`", line.number))?;
+        *pos += 1;
+        let value = value.trim();
+        // Lists may be indented as far as their key.
+        let list_follows = lines
+            .get(*pos)
+            .map_or(false, |l| l.indent == indent && l.text.starts_with('-'));
+        let value = if value.is_empty() {
+            let min_indent = if list_follows { indent } else { indent + 1 };
+            parse_block(lines, pos, min_indent)?.unwrap_or(JsonValue::Null)
+        } else {
+            scalar(value)
+        };
+        map.insert(unquote(key.trim()).to_string(), value);
+    }
+    Ok(Some(JsonValue::Object(map)))
+}
+
+fn is_mapping_entry(s: &str) -> bool {
+    !s.starts_with(['"', '\'']) && (s.ends_with(':') || s.contains(": "))
+}
+
+fn unquote(s: &str) -> &str {
+    for quote in ['"', '\''] {
+        if s.len() >= 2 && s.starts_with(quote) && s.ends_with(quote) {
+            return &s[1..s.len() - 1];
+        }
+    }
+    s
+}
+
+fn scalar(s: &str) -> JsonValue {
+    if s != unquote(s) {
+        return JsonValue::String(unquote(s).to_string());
+    }
+    match s {
+        "true" => JsonValue::Boolean(true),
+        "false" => JsonValue::Boolean(false),
+        "null" | "~" => JsonValue::Null,
+        _ => match s.parse::<f64>() {
+            Ok(n) => JsonValue::Number(n),
+            Err(_) => JsonValue::String(s.to_string()),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn object(entries: Vec<(&str, JsonValue)>) -> JsonValue {
+        JsonValue::Object(
+            entries
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v))
+                .collect(),
+        )
+    }
+
+    fn string(s: &str) -> JsonValue {
+        JsonValue::String(s.to_string())
+    }
+
+    #[test]
+    fn front_matter() {
+        let source = "# A comment
+title: \"Analysis: part 1\"
+version: 1.5
+draft: false
+editor: ~
+jupyter:
+  kernelspec:
+    name: python3
+    language: 'python'
+tags:
+- intro
+- \"10\"
+authors:
+  - name: Ada
+    affiliation: Analytical Engines
+  - name: Grace
+";
+        let expected = object(vec![
+            ("title", string("Analysis: part 1")),
+            ("version", JsonValue::Number(1.5)),
+            ("draft", JsonValue::Boolean(false)),
+            ("editor", JsonValue::Null),
+            (
+                "jupyter",
+                object(vec![(
+                    "kernelspec",
+                    object(vec![
+                        ("name", string("python3")),
+                        ("language", string("python")),
+                    ]),
+                )]),
+            ),
+            (
+                "tags",
+                JsonValue::Array(vec![string("intro"), string("10")]),
+            ),
+            (
+                "authors",
+                JsonValue::Array(vec![
+                    object(vec![
+                        ("name", string("Ada")),
+                        ("affiliation", string("Analytical Engines")),
+                    ]),
+                    object(vec![("name", string("Grace"))]),
+                ]),
+            ),
+        ]);
+        assert_eq!(parse(source), Ok(expected));
+        assert_eq!(parse("\n"), Ok(object(vec![])));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            parse("title: x\nno colon here\n"),
+            Err("line 2: expected `key: value`".to_string())
+        );
+        assert_eq!(
+            parse("  a: 1\nb: 2\n"),
+            Err("line 2: unexpected indentation".to_string())
+        );
+    }
+}